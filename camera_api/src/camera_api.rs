@@ -1,24 +1,45 @@
+use crate::events::{EventPublisher, RecorderEvent};
+use crate::metrics;
+use log::debug;
 use log::error;
 use log::info;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::process::Command;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const TMP_FILE: &str = "/mnt/ram/image_latest.jpg";
 
+/// Calls read after each wait until it succeeds, the last error if none of the attempts does
+fn poll<T, E>(
+    attempts: u32,
+    wait: Duration,
+    mut read: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut attempt = 1;
+    loop {
+        std::thread::sleep(wait);
+        match read() {
+            Err(_) if attempt < attempts => attempt += 1,
+            read => return read,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Camera {
     process_id: u32,
+    events: EventPublisher,
 }
 
 impl Camera {
-    pub fn new() -> Self {
+    pub fn new(events: EventPublisher) -> Self {
         Self::kill_previous_rapistill_process();
-        let new_camera = Self {
-            process_id: Self::start_raspistill_process(),
-        };
+        let process_id = Self::start_raspistill_process();
+        metrics::inc(&metrics::CAMERA_RESTARTS);
+        events.publish(RecorderEvent::CameraStarted { process_id });
+        let new_camera = Self { process_id, events };
         // wait camera process startup
         std::thread::sleep(Duration::from_secs(10));
         new_camera
@@ -26,6 +47,7 @@ impl Camera {
 
     /// This function waits 500ms for the picture to be taken
     pub fn take_new_pic(&self) -> Vec<u8> {
+        let triggered_at = Instant::now();
         let mut process = Command::new("kill")
             .arg("-USR1")
             .arg(format!("{}", self.process_id))
@@ -37,31 +59,52 @@ impl Camera {
             .success()
         {
             error!("Process did not finish successfully.");
+            self.events.publish(RecorderEvent::CaptureError {
+                message: "Error signaling the camera process".to_string(),
+            });
             panic!();
         }
-        for _i in 0..=9 {
-            // time to take picture and write to disk
-            std::thread::sleep(Duration::from_millis(500));
-            match fs::read(TMP_FILE) {
-                Ok(curr_latest) => {
-                    fs::remove_file(TMP_FILE)
-                        .expect(&format!("Error removing tmp file {}", TMP_FILE));
-                    return curr_latest;
-                }
-                Err(e) => {
-                    error!("{}", e);
-                }
+        // time to take picture and write to disk, about a second
+        let read = poll(10, Duration::from_millis(500), || {
+            fs::read(TMP_FILE).map_err(|e| {
+                debug!("Picture not read yet: {}", e);
+                e
+            })
+        });
+        match read {
+            Ok(curr_latest) => {
+                fs::remove_file(TMP_FILE).expect(&format!("Error removing tmp file {}", TMP_FILE));
+                metrics::add_duration(&metrics::CAPTURE_LATENCY_MILLIS, triggered_at.elapsed());
+                return curr_latest;
+            }
+            Err(e) => {
+                // only a capture none of the polls found is a failure
+                metrics::inc(&metrics::CAPTURE_FAILURES);
+                let message = format!("Error reading picture: {}", e);
+                error!("{}", message);
+                self.events.publish(RecorderEvent::CaptureError { message });
             }
         }
         panic!("Failed multiple times to read picture");
     }
 
-    /// This function waits 500ms for the picture to be taken and saved
-    pub fn take_new_pic_save_at(&self, path: &str) {
-        let pic = self.take_new_pic();
+    pub fn save_pic(pic: &[u8], path: &str) {
         let mut f = File::create(path).expect(&format!("Could not create file at {}", path));
-        f.write_all(&pic)
+        f.write_all(pic)
             .expect("Error writing picture to disk at new location");
+        metrics::add(&metrics::BYTES_WRITTEN, pic.len() as u64);
+    }
+
+    /// Stops raspistill, on shutdown
+    pub fn stop(&self) {
+        info!("Stopping raspistill process {}", self.process_id);
+        let stopped = Command::new("kill")
+            .arg(format!("{}", self.process_id))
+            .status()
+            .map(|status| status.success());
+        if stopped.ok() != Some(true) {
+            error!("Could not stop raspistill process {}", self.process_id);
+        }
     }
 
     fn kill_previous_rapistill_process() {
@@ -106,3 +149,34 @@ impl Camera {
         camera_process_id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_returns_the_first_success() {
+        let mut calls = 0;
+        let read = poll(10, Duration::from_millis(0), || {
+            calls += 1;
+            if calls < 3 {
+                Err(calls)
+            } else {
+                Ok("picture")
+            }
+        });
+        assert_eq!(read, Ok("picture"));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn poll_fails_once_every_attempt_did() {
+        let mut calls = 0;
+        let read: Result<(), u32> = poll(10, Duration::from_millis(0), || {
+            calls += 1;
+            Err(calls)
+        });
+        assert_eq!(read, Err(10));
+        assert_eq!(calls, 10);
+    }
+}
@@ -0,0 +1,373 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Optional settings of the recorder, read from this file in the working directory.
+/// See recorder.example.json, every field has a default.
+pub const CONFIG_FILE: &str = "recorder.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecorderConfig {
+    /// usage of the movies disk above which a disk_low event is sent
+    pub disk_low_percent: u8,
+    /// a no_frames_captured event is sent when no frame was captured for this long
+    pub no_frames_minutes: u32,
+    /// where Prometheus metrics are served, without authentication so only locally by default,
+    /// e.g. 0.0.0.0:9101 to expose them to the LAN. Not served if null.
+    pub metrics_address: Option<String>,
+    /// on SIGTERM the current segment is encoded, unless it takes longer than this
+    pub shutdown_deadline_secs: u64,
+    /// name of the encoder profile of the hourly encodes, unless a job names another one
+    pub encoder_profile: String,
+    /// profiles by name, added to the default ones. The fields set by a profile named after a
+    /// default one override those of the default one, e.g. {"x264": {"crf": 28}} keeps its presets.
+    pub encoder_profiles: BTreeMap<String, EncoderProfile>,
+    pub archive_transcode: ArchiveTranscodeConfig,
+    pub frame_dedup: FrameDedupConfig,
+    pub capture_interval: CaptureIntervalConfig,
+    pub webhooks: WebhooksConfig,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            disk_low_percent: 90,
+            no_frames_minutes: 10,
+            metrics_address: Some("127.0.0.1:9101".to_string()),
+            shutdown_deadline_secs: 300,
+            encoder_profile: "x264".to_string(),
+            encoder_profiles: EncoderProfile::defaults(),
+            archive_transcode: ArchiveTranscodeConfig::default(),
+            frame_dedup: FrameDedupConfig::default(),
+            capture_interval: CaptureIntervalConfig::default(),
+            webhooks: WebhooksConfig::default(),
+        }
+    }
+}
+
+/// How ffmpeg encodes a movie. All of them are written to mp4, so the movies stay playable and
+/// stitchable whatever the profile, as long as the stitched clips share their codec.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncoderProfile {
+    /// ffmpeg video encoder, -c:v
+    pub codec: String,
+    /// constant quality, -crf, lower is better
+    pub crf: Option<u32>,
+    /// target bitrate, -b:v, for the encoders without crf like the hardware ones
+    pub bitrate: Option<String>,
+    /// -preset of each encode, by number of encodes waiting: the first one when none is waiting,
+    /// then faster ones as the queue grows so it catches up. No -preset if empty.
+    pub presets: Vec<String>,
+    /// preset an encode is retried with once when ffmpeg fails, not retried if null
+    pub fallback_preset: Option<String>,
+    /// any other ffmpeg output options
+    pub extra_args: Vec<String>,
+}
+
+impl Default for EncoderProfile {
+    fn default() -> Self {
+        Self {
+            codec: "libx264".to_string(),
+            crf: None,
+            bitrate: None,
+            presets: vec![],
+            fallback_preset: None,
+            extra_args: vec![],
+        }
+    }
+}
+
+impl EncoderProfile {
+    fn defaults() -> BTreeMap<String, EncoderProfile> {
+        let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+        let mut profiles = BTreeMap::new();
+        // hardware encoders of the Pi, h264_omx until Buster and h264_v4l2m2m since Bullseye
+        profiles.insert(
+            "h264_omx".to_string(),
+            EncoderProfile {
+                codec: "h264_omx".to_string(),
+                bitrate: Some("1.2M".to_string()),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "h264_v4l2m2m".to_string(),
+            EncoderProfile {
+                codec: "h264_v4l2m2m".to_string(),
+                bitrate: Some("1.2M".to_string()),
+                // the encoder does not take the yuvj420p of the jpegs
+                extra_args: strings(&["-pix_fmt", "yuv420p"]),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "x264".to_string(),
+            EncoderProfile {
+                codec: "libx264".to_string(),
+                crf: Some(32),
+                presets: strings(&["slow", "medium", "fast", "veryfast"]),
+                fallback_preset: Some("ultrafast".to_string()),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "x265".to_string(),
+            EncoderProfile {
+                codec: "libx265".to_string(),
+                crf: Some(30),
+                presets: strings(&["medium", "fast", "veryfast"]),
+                fallback_preset: Some("ultrafast".to_string()),
+                // the tag Safari expects for HEVC in mp4
+                extra_args: strings(&["-tag:v", "hvc1"]),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "vp9".to_string(),
+            EncoderProfile {
+                codec: "libvpx-vp9".to_string(),
+                crf: Some(36),
+                // constant quality mode of libvpx
+                bitrate: Some("0".to_string()),
+                extra_args: strings(&["-deadline", "good", "-cpu-used", "4", "-row-mt", "1"]),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "av1".to_string(),
+            EncoderProfile {
+                codec: "libsvtav1".to_string(),
+                crf: Some(38),
+                presets: strings(&["8", "10", "12"]),
+                fallback_preset: Some("13".to_string()),
+                ..Default::default()
+            },
+        );
+        profiles
+    }
+
+    /// The default profiles, with the fields of the profiles of the config over them
+    fn with_overrides(overrides: Option<&Value>) -> BTreeMap<String, EncoderProfile> {
+        let mut profiles = Self::defaults();
+        let overrides = match overrides.and_then(Value::as_object) {
+            Some(overrides) => overrides,
+            None => return profiles,
+        };
+        for (name, fields) in overrides {
+            let mut profile = match profiles.get(name) {
+                Some(profile) => serde_json::to_value(profile).expect("Error serializing profile"),
+                None => Value::Object(Default::default()),
+            };
+            if let (Some(profile), Some(fields)) = (profile.as_object_mut(), fields.as_object()) {
+                profile.extend(fields.clone());
+            }
+            let profile = serde_json::from_value(profile).unwrap_or_else(|e| {
+                panic!("Invalid encoder profile {} in {}: {}", name, CONFIG_FILE, e)
+            });
+            profiles.insert(name.clone(), profile);
+        }
+        profiles
+    }
+
+    /// ffmpeg output options encoding the video with this profile
+    pub fn ffmpeg_args(&self, preset: Option<&str>) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), self.codec.clone()];
+        if let Some(preset) = preset {
+            args.extend(vec!["-preset".to_string(), preset.to_string()]);
+        }
+        if let Some(crf) = self.crf {
+            args.extend(vec!["-crf".to_string(), crf.to_string()]);
+        }
+        if let Some(bitrate) = &self.bitrate {
+            args.extend(vec!["-b:v".to_string(), bitrate.clone()]);
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+/// Re-encoding of the day movies of the archive to a denser profile, while the Pi is idle
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveTranscodeConfig {
+    /// encoder profile the day movies are re-encoded with, disabled if null
+    pub profile: Option<String>,
+    /// local hour transcodes may start at
+    pub start_hour: u32,
+    /// local hour a running transcode is stopped at, to be retried the next night
+    pub end_hour: u32,
+    /// day movies younger than this are left alone, they are the most watched
+    pub min_age_days: u32,
+    /// a movie is not transcoded again after this many attempts, e.g. when it takes longer than
+    /// the hours
+    pub max_attempts: u32,
+}
+
+impl Default for ArchiveTranscodeConfig {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            start_hour: 2,
+            end_hour: 5,
+            min_age_days: 7,
+            max_attempts: 3,
+        }
+    }
+}
+
+impl ArchiveTranscodeConfig {
+    /// Whether the hour is in the idle window, which may wrap around midnight
+    pub fn in_window(&self, hour: u32) -> bool {
+        if self.start_hour <= self.end_hour {
+            self.start_hour <= hour && hour < self.end_hour
+        } else {
+            self.start_hour <= hour || hour < self.end_hour
+        }
+    }
+}
+
+/// Dropping of the frames nearly identical to the last kept one, so the static hours, like the
+/// dark kitchen at night, take a few seconds of the day movie
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameDedupConfig {
+    /// every frame is kept if false
+    pub enabled: bool,
+    /// a pixel changed if its luma moved by more than this, out of 255, above the sensor noise
+    pub pixel_threshold: u8,
+    /// a frame is dropped if at most this percentage of its pixels changed
+    pub changed_percent: f64,
+    /// one frame of this many nearly identical ones is kept anyway, so time still passes in the
+    /// movie, 0 drops them all
+    pub keep_every: u32,
+    /// percentage of the rows at the top not compared, raspistill annotates the time there
+    pub ignore_top_percent: u32,
+}
+
+impl Default for FrameDedupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            pixel_threshold: 20,
+            changed_percent: 1.0,
+            keep_every: 30,
+            ignore_top_percent: 6,
+        }
+    }
+}
+
+/// Interval between captures, short while something moves and long while the scene is still. The
+/// pixels are compared as set in frame_dedup.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureIntervalConfig {
+    /// frames are captured every min_interval_millis if false
+    pub adaptive: bool,
+    /// interval while there is motion, 0 captures as fast as the camera does, about a second
+    pub min_interval_millis: u64,
+    /// interval the still scene ramps down to, keep it well below the WatchdogSec of the unit
+    pub max_interval_millis: u64,
+    /// percentage of pixels changed since the previous frame above which there is motion
+    pub motion_percent: f64,
+    /// percentage at or below which the scene is still, lower than motion_percent so the
+    /// interval holds between the two
+    pub still_percent: f64,
+    /// still frames in a row before the interval starts growing
+    pub still_frames: u32,
+}
+
+impl Default for CaptureIntervalConfig {
+    fn default() -> Self {
+        Self {
+            adaptive: true,
+            min_interval_millis: 0,
+            max_interval_millis: 10_000,
+            motion_percent: 2.0,
+            still_percent: 0.5,
+            still_frames: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    /// every event is POSTed as JSON to each of these
+    pub urls: Vec<String>,
+    /// event types to send, all but frame_captured and the segment ones if empty
+    pub events: Vec<String>,
+    /// key of the HMAC-SHA256 of the body, sent in the X-Timelapse-Signature header
+    pub secret: Option<String>,
+    pub max_attempts: u32,
+    /// JSON lines file recording every delivery attempt, moved to <delivery_log>.1 past 1 MB
+    pub delivery_log: String,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            events: vec![],
+            secret: None,
+            max_attempts: 5,
+            delivery_log: "./logs/webhook_deliveries.jsonl".to_string(),
+        }
+    }
+}
+
+impl RecorderConfig {
+    pub fn load() -> Self {
+        match fs::read_to_string(CONFIG_FILE) {
+            Ok(config) => {
+                info!("Loading config from {}", CONFIG_FILE);
+                let raw: Value = serde_json::from_str(&config)
+                    .expect(&format!("Invalid config {}", CONFIG_FILE));
+                let mut config: Self = serde_json::from_value(raw.clone())
+                    .unwrap_or_else(|e| panic!("Invalid config {}: {}", CONFIG_FILE, e));
+                config.encoder_profiles =
+                    EncoderProfile::with_overrides(raw.get("encoder_profiles"));
+                for (name, profile) in &config.encoder_profiles {
+                    if profile.crf.is_none() && profile.bitrate.is_none() {
+                        panic!(
+                            "Encoder profile {} of {} sets neither crf nor bitrate",
+                            name, CONFIG_FILE
+                        );
+                    }
+                }
+                config.profile(&config.encoder_profile).expect(&format!(
+                    "Unknown encoder_profile {} in {}",
+                    config.encoder_profile, CONFIG_FILE
+                ));
+                if let Some(profile) = &config.archive_transcode.profile {
+                    config.profile(profile).expect(&format!(
+                        "Unknown archive_transcode profile {} in {}",
+                        profile, CONFIG_FILE
+                    ));
+                }
+                let interval = &config.capture_interval;
+                if interval.min_interval_millis > interval.max_interval_millis
+                    || interval.still_percent > interval.motion_percent
+                {
+                    panic!(
+                        "capture_interval of {} needs min_interval_millis <= max_interval_millis \
+                         and still_percent <= motion_percent",
+                        CONFIG_FILE
+                    );
+                }
+                config
+            }
+            Err(_) => {
+                info!("No {}, using the default config", CONFIG_FILE);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&EncoderProfile> {
+        self.encoder_profiles.get(name)
+    }
+}
@@ -0,0 +1,44 @@
+use log::error;
+use std::process::Command;
+
+/// Percentage of the filesystem holding path which is used, as reported by df
+pub fn disk_usage_percent(path: &str) -> Option<u8> {
+    let output = match Command::new("df").arg("--output=pcent").arg(path).output() {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Could not run df on {}: {}", path, e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        error!(
+            "df on {} failed: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return None;
+    }
+    // Use%
+    //  42%
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .and_then(|line| line.trim().trim_end_matches('%').parse().ok())
+}
+
+/// Bytes available to unprivileged users on the filesystem holding path, as reported by df
+pub fn disk_available_bytes(path: &str) -> Option<u64> {
+    let output = Command::new("df")
+        .arg("--output=avail")
+        .arg("-B1")
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .and_then(|line| line.trim().parse().ok())
+}
@@ -0,0 +1,261 @@
+use crate::config::RecorderConfig;
+use crate::status::{start_status_writer, RecorderStatus};
+use crate::timelapse::FfmpegProgress;
+use crate::webhooks::Webhooks;
+use chrono::{Local, Timelike};
+use log::error;
+use serde::Serialize;
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Datagram socket video_streaming_api listens on to forward recorder events to its /events
+/// clients. It lives in the RAM disk, next to the latest picture.
+pub const EVENTS_SOCKET: &str = "/mnt/ram/recorder_events.sock";
+
+/// Something that happened in the recorder, sent as JSON with its kind in the "type" field
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecorderEvent {
+    /// also sent for the frames dropped as nearly identical to the last kept one, frame is then
+    /// the number the next kept frame will have
+    FrameCaptured {
+        frame: u32,
+        /// unix timestamp in milliseconds
+        captured_at: i64,
+        kept: bool,
+    },
+    SegmentStarted {
+        started_at: i64,
+    },
+    SegmentEnded {
+        /// kept in the segment
+        frames: u32,
+        dropped: u32,
+    },
+    EncodeStarted {
+        movie: String,
+    },
+    EncodeProgress(EncodeProgress),
+    /// an hourly clip is available in the today folder, path relative to the movies folder
+    EncodeFinished {
+        movie: String,
+    },
+    /// the pictures of the segment were moved to the failed segments folder
+    EncodeFailed {
+        movie: String,
+        exit_code: Option<i32>,
+        /// last lines ffmpeg printed
+        stderr_tail: String,
+    },
+    /// day is the timestamp of the today folder being stitched
+    StitchStarted {
+        day: i64,
+    },
+    StitchFinished {
+        movie: String,
+    },
+    /// the day movie was moved to the quarantine folder, the hourly clips stay in the today folder
+    StitchFailed {
+        day: i64,
+        reason: String,
+    },
+    /// a day movie of the archive was replaced by its re-encode
+    ArchiveTranscoded {
+        movie: String,
+        /// codec of the new movie, as named by ffprobe
+        codec: String,
+        bytes_before: u64,
+        bytes_after: u64,
+    },
+    /// the original movie is kept
+    ArchiveTranscodeFailed {
+        movie: String,
+        reason: String,
+    },
+    /// an encode or stitch job errored or panicked and was moved to the failed jobs, whatever
+    /// it was doing is over
+    JobFailed {
+        /// the job, as in jobs.json
+        job: String,
+        reason: String,
+    },
+    /// the raspistill process was (re)started
+    CameraStarted {
+        process_id: u32,
+    },
+    CaptureError {
+        message: String,
+    },
+    /// sent once when capture stalls, until frames are captured again
+    NoFramesCaptured {
+        /// unix timestamp in milliseconds
+        last_frame_at: i64,
+    },
+    DiskLow {
+        path: String,
+        used_percent: u8,
+    },
+}
+
+/// Progress of the running hourly encode, from the ffmpeg -progress output
+#[derive(Clone, Debug, Serialize)]
+pub struct EncodeProgress {
+    pub movie: String,
+    pub frame: u64,
+    /// pictures of the segment
+    pub total_frames: u64,
+    pub fps: f64,
+    /// position in the movie
+    pub out_time_millis: i64,
+    /// relative to playback, 2.0 when ffmpeg reports 2x
+    pub speed: Option<f64>,
+    /// at the current fps, unknown until ffmpeg reports one
+    pub eta_secs: Option<u64>,
+    /// unix timestamp in milliseconds, the segment being recorded ends with the hour
+    pub segment_ends_at: i64,
+    /// when not, the next encode is queued behind this one and the backlog grows
+    pub finishes_before_segment_end: Option<bool>,
+}
+
+impl EncodeProgress {
+    pub fn new(movie: &str, total_frames: u64, progress: FfmpegProgress) -> Self {
+        let now = Local::now();
+        let segment_ends_at = now
+            .with_minute(0)
+            .and_then(|now| now.with_second(0))
+            .and_then(|now| now.with_nanosecond(0))
+            .expect("Start of the hour is a valid time")
+            + chrono::Duration::hours(1);
+        let frames_left = total_frames.saturating_sub(progress.frame);
+        let eta_secs = if progress.fps > 0.0 {
+            Some((frames_left as f64 / progress.fps).ceil() as u64)
+        } else {
+            None
+        };
+        let finishes_before_segment_end = eta_secs.map(|eta_secs| {
+            now.timestamp_millis() + eta_secs as i64 * 1000 <= segment_ends_at.timestamp_millis()
+        });
+        Self {
+            movie: movie.to_string(),
+            frame: progress.frame,
+            total_frames,
+            fps: progress.fps,
+            out_time_millis: progress.out_time_millis,
+            speed: progress.speed,
+            eta_secs,
+            segment_ends_at: segment_ends_at.timestamp_millis(),
+            finishes_before_segment_end,
+        }
+    }
+}
+
+impl RecorderEvent {
+    /// Value of the "type" field
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            RecorderEvent::FrameCaptured { .. } => "frame_captured",
+            RecorderEvent::SegmentStarted { .. } => "segment_started",
+            RecorderEvent::SegmentEnded { .. } => "segment_ended",
+            RecorderEvent::EncodeStarted { .. } => "encode_started",
+            RecorderEvent::EncodeProgress(_) => "encode_progress",
+            RecorderEvent::EncodeFinished { .. } => "encode_finished",
+            RecorderEvent::EncodeFailed { .. } => "encode_failed",
+            RecorderEvent::StitchStarted { .. } => "stitch_started",
+            RecorderEvent::StitchFinished { .. } => "stitch_finished",
+            RecorderEvent::StitchFailed { .. } => "stitch_failed",
+            RecorderEvent::ArchiveTranscoded { .. } => "archive_transcoded",
+            RecorderEvent::ArchiveTranscodeFailed { .. } => "archive_transcode_failed",
+            RecorderEvent::JobFailed { .. } => "job_failed",
+            RecorderEvent::CameraStarted { .. } => "camera_started",
+            RecorderEvent::CaptureError { .. } => "capture_error",
+            RecorderEvent::NoFramesCaptured { .. } => "no_frames_captured",
+            RecorderEvent::DiskLow { .. } => "disk_low",
+        }
+    }
+}
+
+/// Sends events to EVENTS_SOCKET and to the webhooks, and keeps the recorder status up to date.
+/// Events are dropped when nobody listens, recording never waits for the API.
+#[derive(Clone, Debug)]
+pub struct EventPublisher {
+    socket: Option<Arc<UnixDatagram>>,
+    webhooks: Option<Webhooks>,
+    status: Arc<Mutex<RecorderStatus>>,
+}
+
+impl EventPublisher {
+    pub fn new(config: &RecorderConfig) -> Self {
+        let socket = match UnixDatagram::unbound() {
+            Ok(socket) => {
+                socket
+                    .set_nonblocking(true)
+                    .expect("Error setting events socket non blocking");
+                Some(Arc::new(socket))
+            }
+            Err(e) => {
+                error!("Could not create events socket, events are disabled: {}", e);
+                None
+            }
+        };
+        let webhooks = if config.webhooks.urls.is_empty() {
+            None
+        } else {
+            Some(Webhooks::start(config.webhooks.clone()))
+        };
+        let status = Arc::new(Mutex::new(RecorderStatus::new()));
+        start_status_writer(status.clone());
+        let publisher = Self {
+            socket,
+            webhooks,
+            status,
+        };
+        publisher.start_no_frames_watchdog(config.no_frames_minutes);
+        publisher
+    }
+
+    pub fn publish(&self, event: RecorderEvent) {
+        self.status
+            .lock()
+            .expect("Status lock poisoned")
+            .apply(&event);
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.send(&event);
+        }
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return,
+        };
+        let event = serde_json::to_vec(&event).expect("Error serializing recorder event");
+        // fails whenever the API is not running, nothing to do about it
+        let _ = socket.send_to(&event, EVENTS_SOCKET);
+    }
+
+    /// Checks every minute whether a frame was captured in the last no_frames_minutes
+    fn start_no_frames_watchdog(&self, no_frames_minutes: u32) {
+        let publisher = self.clone();
+        let max_silence_millis = no_frames_minutes as i64 * 60 * 1000;
+        std::thread::spawn(move || {
+            let mut reported_frame = None;
+            loop {
+                std::thread::sleep(Duration::from_secs(60));
+                let last_frame_at = publisher
+                    .status
+                    .lock()
+                    .expect("Status lock poisoned")
+                    .last_frame_at();
+                let last_frame_at = match last_frame_at {
+                    Some(last_frame_at) => last_frame_at,
+                    None => continue,
+                };
+                let silence = chrono::Local::now().timestamp_millis() - last_frame_at;
+                if silence < max_silence_millis || reported_frame == Some(last_frame_at) {
+                    continue;
+                }
+                error!("No frame captured for {} seconds", silence / 1000);
+                reported_frame = Some(last_frame_at);
+                publisher.publish(RecorderEvent::NoFramesCaptured { last_frame_at });
+            }
+        });
+    }
+}
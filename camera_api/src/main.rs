@@ -2,6 +2,14 @@
 //ffmpeg -framerate 10 -i image7_%04d.jpg -video_size 1640:1232 -c:v h264_omx -bufsize 64k -b:v 1.2M -vf fps=10 out.mp4
 use flexi_logger::{Cleanup, Criterion, Naming};
 mod camera_api;
+mod config;
+mod disk;
+mod events;
+mod metrics;
+mod shutdown;
+mod status;
+mod systemd;
+mod webhooks;
 
 mod timelapse;
 
@@ -21,6 +29,14 @@ fn main() {
         .unwrap();
     log_panics::init();
     info!("Starting up...");
-    let mut timelapse_manufacturer = timelapse::TimeLapseManufacturer::new();
+    let shutdown = shutdown::register_signals();
+    let config = config::RecorderConfig::load();
+    shutdown::enforce_deadline(
+        shutdown.clone(),
+        std::time::Duration::from_secs(config.shutdown_deadline_secs),
+    );
+    let mut timelapse_manufacturer = timelapse::TimeLapseManufacturer::new(config, shutdown);
+    // the camera is warmed up
+    systemd::notify("READY=1\nSTATUS=Camera ready");
     timelapse_manufacturer.run();
 }
@@ -0,0 +1,201 @@
+use crate::disk::{disk_available_bytes, disk_usage_percent};
+use log::{error, info};
+use std::fmt::Write as FmtWrite;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub static FRAMES_CAPTURED: AtomicU64 = AtomicU64::new(0);
+/// captured but nearly identical to the last kept frame
+pub static FRAMES_DROPPED: AtomicU64 = AtomicU64::new(0);
+pub static CAPTURE_FAILURES: AtomicU64 = AtomicU64::new(0);
+pub static CAPTURE_LATENCY_MILLIS: AtomicU64 = AtomicU64::new(0);
+pub static CAMERA_RESTARTS: AtomicU64 = AtomicU64::new(0);
+pub static ENCODES: AtomicU64 = AtomicU64::new(0);
+pub static ENCODE_MILLIS: AtomicU64 = AtomicU64::new(0);
+pub static ENCODE_FAILURES: AtomicU64 = AtomicU64::new(0);
+pub static STITCHES: AtomicU64 = AtomicU64::new(0);
+pub static STITCH_MILLIS: AtomicU64 = AtomicU64::new(0);
+pub static STITCH_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// set after each capture, see timelapse/interval.rs
+pub static CAPTURE_INTERVAL_MILLIS: AtomicU64 = AtomicU64::new(0);
+/// pictures, movies and stitched days written to disk
+pub static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+pub fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn add(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+pub fn set(gauge: &AtomicU64, value: u64) {
+    gauge.store(value, Ordering::Relaxed);
+}
+
+pub fn add_duration(counter: &AtomicU64, duration: Duration) {
+    add(counter, duration.as_millis() as u64);
+}
+
+fn value(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Summaries with only _sum and _count, durations are stored in milliseconds
+fn summary(out: &mut String, name: &str, help: &str, sum_millis: u64, count: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} summary", name);
+    let _ = writeln!(out, "{}_sum {}", name, sum_millis as f64 / 1000.);
+    let _ = writeln!(out, "{}_count {}", name, count);
+}
+
+/// Prometheus text exposition of the recorder metrics
+pub fn render(movies_folder: &str) -> String {
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "timelapse_frames_captured_total",
+        "counter",
+        "Frames captured",
+        value(&FRAMES_CAPTURED) as f64,
+    );
+    metric(
+        &mut out,
+        "timelapse_frames_dropped_total",
+        "counter",
+        "Frames dropped as nearly identical to the last kept one",
+        value(&FRAMES_DROPPED) as f64,
+    );
+    metric(
+        &mut out,
+        "timelapse_capture_failures_total",
+        "counter",
+        "Pictures the camera did not write within 5 seconds of the capture",
+        value(&CAPTURE_FAILURES) as f64,
+    );
+    summary(
+        &mut out,
+        "timelapse_capture_latency_seconds",
+        "Time from triggering the camera to reading the picture",
+        value(&CAPTURE_LATENCY_MILLIS),
+        value(&FRAMES_CAPTURED),
+    );
+    metric(
+        &mut out,
+        "timelapse_capture_interval_seconds",
+        "gauge",
+        "Interval to the next capture, long while the scene is still",
+        value(&CAPTURE_INTERVAL_MILLIS) as f64 / 1000.,
+    );
+    metric(
+        &mut out,
+        "timelapse_camera_restarts_total",
+        "counter",
+        "raspistill processes started",
+        value(&CAMERA_RESTARTS) as f64,
+    );
+    summary(
+        &mut out,
+        "timelapse_encode_duration_seconds",
+        "Time spent encoding hourly clips",
+        value(&ENCODE_MILLIS),
+        value(&ENCODES),
+    );
+    summary(
+        &mut out,
+        "timelapse_stitch_duration_seconds",
+        "Time spent stitching days",
+        value(&STITCH_MILLIS),
+        value(&STITCHES),
+    );
+    let _ = writeln!(
+        out,
+        "# HELP timelapse_ffmpeg_failures_total ffmpeg runs which exited with an error"
+    );
+    let _ = writeln!(out, "# TYPE timelapse_ffmpeg_failures_total counter");
+    let _ = writeln!(
+        out,
+        "timelapse_ffmpeg_failures_total{{job=\"encode\"}} {}",
+        value(&ENCODE_FAILURES)
+    );
+    let _ = writeln!(
+        out,
+        "timelapse_ffmpeg_failures_total{{job=\"stitch\"}} {}",
+        value(&STITCH_FAILURES)
+    );
+    metric(
+        &mut out,
+        "timelapse_bytes_written_total",
+        "counter",
+        "Bytes of pictures and movies written",
+        value(&BYTES_WRITTEN) as f64,
+    );
+    if let Some(available) = disk_available_bytes(movies_folder) {
+        metric(
+            &mut out,
+            "timelapse_disk_available_bytes",
+            "gauge",
+            "Free space of the movies disk",
+            available as f64,
+        );
+    }
+    if let Some(used_percent) = disk_usage_percent(movies_folder) {
+        metric(
+            &mut out,
+            "timelapse_disk_used_ratio",
+            "gauge",
+            "Used fraction of the movies disk",
+            used_percent as f64 / 100.,
+        );
+    }
+    out
+}
+
+/// Serves the metrics to any HTTP request on address, from a background thread
+pub fn serve(address: &str, movies_folder: &'static str) {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "Could not listen on {}, metrics are disabled: {}",
+                address, e
+            );
+            return;
+        }
+    };
+    info!("Serving metrics on {}", address);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream, movies_folder) {
+                        error!("Error serving metrics: {}", e);
+                    }
+                }
+                Err(e) => error!("Error accepting metrics connection: {}", e),
+            }
+        }
+    });
+}
+
+fn respond(mut stream: TcpStream, movies_folder: &str) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // the request is not looked at, every path returns the metrics
+    let mut request = [0; 4096];
+    let _ = stream.read(&mut request)?;
+    let body = render(movies_folder);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
@@ -0,0 +1,33 @@
+use log::{error, info};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Flag set when SIGTERM or SIGINT is received, the recorder then finishes the current segment
+/// and exits
+pub fn register_signals() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in &[SIGTERM, SIGINT] {
+        signal_hook::flag::register(*signal, Arc::clone(&shutdown))
+            .expect("Error registering signal handler");
+    }
+    shutdown
+}
+
+/// Exits the process if it is still running deadline after the shutdown was requested,
+/// giving up on the segment being encoded
+pub fn enforce_deadline(shutdown: Arc<AtomicBool>, deadline: Duration) {
+    std::thread::spawn(move || {
+        while !shutdown.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        info!(
+            "Shutdown requested, exiting in at most {} seconds",
+            deadline.as_secs()
+        );
+        std::thread::sleep(deadline);
+        error!("Shutdown deadline exceeded, exiting without finishing the segment");
+        std::process::exit(1);
+    });
+}
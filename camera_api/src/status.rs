@@ -0,0 +1,132 @@
+use crate::events::{EncodeProgress, RecorderEvent};
+use log::error;
+use serde::Serialize;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Written periodically for video_streaming_api /health, in the RAM disk so it doesn't wear
+/// the SD card
+pub const STATUS_FILE: &str = "/mnt/ram/recorder_status.json";
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What the recorder threads are doing, derived from the events they publish.
+/// All times are unix timestamps in milliseconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecorderStatus {
+    /// when the status file was written, stale if the recorder is dead
+    updated_at: i64,
+    started_at: i64,
+    last_frame_at: Option<i64>,
+    /// set while the picture thread takes pictures
+    segment_started_at: Option<i64>,
+    /// set while the encoding thread runs
+    encoding_started_at: Option<i64>,
+    /// latest progress of the running encode
+    encode_progress: Option<EncodeProgress>,
+    stitching_started_at: Option<i64>,
+}
+
+impl RecorderStatus {
+    pub fn new() -> Self {
+        let now = chrono::Local::now().timestamp_millis();
+        Self {
+            updated_at: now,
+            started_at: now,
+            last_frame_at: None,
+            segment_started_at: None,
+            encoding_started_at: None,
+            encode_progress: None,
+            stitching_started_at: None,
+        }
+    }
+
+    pub fn last_frame_at(&self) -> Option<i64> {
+        self.last_frame_at
+    }
+
+    pub fn apply(&mut self, event: &RecorderEvent) {
+        let now = chrono::Local::now().timestamp_millis();
+        match event {
+            RecorderEvent::FrameCaptured { captured_at, .. } => {
+                self.last_frame_at = Some(*captured_at)
+            }
+            RecorderEvent::SegmentStarted { started_at } => {
+                self.segment_started_at = Some(*started_at)
+            }
+            RecorderEvent::SegmentEnded { .. } => self.segment_started_at = None,
+            RecorderEvent::EncodeStarted { .. } => {
+                self.encoding_started_at = Some(now);
+                self.encode_progress = None;
+            }
+            RecorderEvent::EncodeProgress(progress) => {
+                self.encode_progress = Some(progress.clone())
+            }
+            RecorderEvent::EncodeFinished { .. } | RecorderEvent::EncodeFailed { .. } => {
+                self.encoding_started_at = None;
+                self.encode_progress = None;
+            }
+            RecorderEvent::StitchStarted { .. } => self.stitching_started_at = Some(now),
+            RecorderEvent::StitchFinished { .. } | RecorderEvent::StitchFailed { .. } => {
+                self.stitching_started_at = None
+            }
+            // the job may have failed after the encode or stitch events it sends on success
+            RecorderEvent::JobFailed { .. } => {
+                self.encoding_started_at = None;
+                self.encode_progress = None;
+                self.stitching_started_at = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes the status to STATUS_FILE every STATUS_INTERVAL from a background thread
+pub fn start_status_writer(status: Arc<Mutex<RecorderStatus>>) {
+    std::thread::spawn(move || loop {
+        let json = {
+            let mut status = status.lock().expect("Status lock poisoned");
+            status.updated_at = chrono::Local::now().timestamp_millis();
+            serde_json::to_string(&*status).expect("Error serializing status")
+        };
+        let tmp_path = format!("{}.tmp", STATUS_FILE);
+        let written = fs::write(&tmp_path, json).and_then(|_| fs::rename(&tmp_path, STATUS_FILE));
+        if let Err(e) = written {
+            error!("Error writing {}: {}", STATUS_FILE, e);
+        }
+        std::thread::sleep(STATUS_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_job_ends_the_encode() {
+        let mut status = RecorderStatus::new();
+        status.apply(&RecorderEvent::EncodeStarted {
+            movie: "1614861000.mp4".to_string(),
+        });
+        assert!(status.encoding_started_at.is_some());
+        // the encode succeeded, but moving the movie to the today folder failed
+        status.apply(&RecorderEvent::JobFailed {
+            job: r#"{"type":"encode","segment":1614861000}"#.to_string(),
+            reason: "Error moving the movie".to_string(),
+        });
+        assert!(status.encoding_started_at.is_none());
+        assert!(status.encode_progress.is_none());
+    }
+
+    #[test]
+    fn failed_job_ends_the_stitch() {
+        let mut status = RecorderStatus::new();
+        status.apply(&RecorderEvent::StitchStarted { day: 1614812400 });
+        assert!(status.stitching_started_at.is_some());
+        status.apply(&RecorderEvent::JobFailed {
+            job: r#"{"type":"stitch"}"#.to_string(),
+            reason: "panicked".to_string(),
+        });
+        assert!(status.stitching_started_at.is_none());
+    }
+}
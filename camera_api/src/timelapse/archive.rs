@@ -0,0 +1,252 @@
+use crate::config::RecorderConfig;
+use crate::events::{EventPublisher, RecorderEvent};
+use crate::metrics;
+use crate::timelapse::encoder::read_tail;
+use crate::timelapse::jobs::JobQueue;
+use crate::timelapse::metadata::{metadata_filename, MovieMetadata, Transcoded};
+use crate::timelapse::probe::{probe, probed_codec, quarantine, verify, Expected};
+use crate::timelapse::{TimeLapseManufacturer, ENCODING_FOLDER, MOVIES_FOLDER_ROOT};
+use chrono::prelude::*;
+use log::{error, info};
+use std::collections::HashSet;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the archive transcode checks whether it may start
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often a running transcode checks whether it must stop
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Re-encodes the day movies of the archive with the archive_transcode profile in its own thread,
+/// so the jobs of the JobRunner never wait for it. Its ffmpeg is niced, the hourly encodes queued
+/// meanwhile come first.
+pub struct ArchiveTranscoder {
+    config: RecorderConfig,
+    jobs: JobQueue,
+    events: EventPublisher,
+    /// set by SIGTERM and SIGINT, stops the running transcode
+    shutdown: Arc<AtomicBool>,
+}
+
+fn metadata_path(movie: i64) -> String {
+    format!(
+        "{}/{}",
+        MOVIES_FOLDER_ROOT,
+        metadata_filename(&movie.to_string())
+    )
+}
+
+impl ArchiveTranscoder {
+    pub fn new(
+        config: RecorderConfig,
+        jobs: JobQueue,
+        events: EventPublisher,
+        shutdown: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            config,
+            jobs,
+            events,
+            shutdown,
+        }
+    }
+
+    /// Transcodes one day movie at a time while in the idle window and nothing is queued. Each
+    /// movie is tried at most once a night, and max_attempts times in all. The thread ends on
+    /// shutdown, None if the archive transcode is disabled.
+    pub fn start(self) -> Option<JoinHandle<()>> {
+        let config = self.config.archive_transcode.clone();
+        if config.profile.is_none() {
+            info!("Archive transcode disabled");
+            return None;
+        }
+        Some(std::thread::spawn(move || {
+            let mut tried = HashSet::new();
+            loop {
+                let waiting_since = Instant::now();
+                while waiting_since.elapsed() < CHECK_INTERVAL {
+                    if self.shutdown.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+                if !config.in_window(Local::now().hour()) {
+                    tried.clear();
+                    continue;
+                }
+                if !self.jobs.is_empty() {
+                    continue;
+                }
+                let oldest_allowed =
+                    Local::now().timestamp() - config.min_age_days as i64 * 24 * 3600;
+                let mut movies = TimeLapseManufacturer::get_dir_structure().movies;
+                movies.sort_by_key(|movie| movie.timestamp);
+                let next = movies.into_iter().find(|movie| {
+                    let metadata = MovieMetadata::read(&metadata_path(movie.timestamp));
+                    movie.timestamp < oldest_allowed
+                        && !tried.contains(&movie.timestamp)
+                        && metadata.transcoded.is_none()
+                        && metadata.transcode_attempts.unwrap_or(0) < config.max_attempts
+                });
+                if let Some(movie) = next {
+                    tried.insert(movie.timestamp);
+                    if let Err(e) = self.transcode(movie.timestamp) {
+                        error!("Error transcoding {}: {}", movie.path, e);
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Re-encodes a day movie, replacing it only once the re-encode has the same duration
+    fn transcode(&self, movie: i64) -> Result<(), String> {
+        let config = &self.config.archive_transcode;
+        let profile_name = match &config.profile {
+            Some(profile) => profile.clone(),
+            None => return Ok(()),
+        };
+        #[cfg(feature = "native-encoder")]
+        {
+            if crate::timelapse::native::ffmpeg_missing() {
+                error!("Cannot transcode {} without ffmpeg", movie);
+                return Ok(());
+            }
+        }
+        let movie_path = format!("{}/{}.mp4", MOVIES_FOLDER_ROOT, movie);
+        let filename = format!("{}.mp4", movie);
+        let original = match fs::metadata(&movie_path)
+            .ok()
+            .and_then(|_| probe(&movie_path))
+        {
+            Some(original) => original,
+            None => {
+                error!("Cannot probe {}, not transcoding it", movie_path);
+                return Ok(());
+            }
+        };
+        let profile = self
+            .config
+            .profile(&profile_name)
+            .expect("Archive transcode profile is checked on load")
+            .clone();
+        // counted before starting, so a movie that cannot be transcoded within the hours, or
+        // crashes the recorder, is given up on
+        let mut metadata = MovieMetadata::read(&metadata_path(movie));
+        let attempt = metadata.transcode_attempts.unwrap_or(0) + 1;
+        metadata.transcode_attempts = Some(attempt);
+        metadata.write(&metadata_path(movie));
+        fs::create_dir_all(ENCODING_FOLDER)
+            .map_err(|e| format!("Error creating {}: {}", ENCODING_FOLDER, e))?;
+        let out_path = format!("{}/{}.transcode.mp4", ENCODING_FOLDER, movie);
+        info!(
+            "Transcoding {} with profile {}, attempt {} of {}",
+            movie_path, profile_name, attempt, config.max_attempts
+        );
+        let mut process = Command::new("nice")
+            .arg("-n")
+            .arg("19")
+            .arg("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(&movie_path)
+            // keeps the capture time subtitles
+            .arg("-map")
+            .arg("0")
+            .arg("-c")
+            .arg("copy")
+            // the fastest preset, a day movie takes hours on a Pi even so
+            .args(profile.ffmpeg_args(profile.presets.last().map(String::as_str)))
+            .arg("-nostats")
+            .arg(&out_path)
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Error starting ffmpeg: {}", e))?;
+        let stderr_tail = read_tail(process.stderr.take().expect("ffmpeg stderr is piped"));
+        let status = loop {
+            if let Some(status) = process
+                .try_wait()
+                .map_err(|e| format!("Error waiting for ffmpeg: {}", e))?
+            {
+                break Some(status);
+            }
+            if self.shutdown.load(Ordering::Relaxed) || !config.in_window(Local::now().hour()) {
+                info!("Stopping the transcode of {}", movie_path);
+                let _ = process.kill();
+                let _ = process.wait();
+                break None;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        let stderr_tail = stderr_tail.join().unwrap_or_default();
+        let status = match status {
+            Some(status) => status,
+            None => {
+                // tried again another night, unless it was the last attempt
+                let _ = fs::remove_file(&out_path);
+                return Ok(());
+            }
+        };
+        let expected = Expected {
+            duration_secs: Some(original.duration_secs),
+            codec: probed_codec(&profile.codec),
+            resolution: original.width.zip(original.height),
+        };
+        let verified = if !status.success() {
+            error!("Transcoding process failed: {}", status);
+            error!("{}", stderr_tail);
+            let _ = fs::remove_file(&out_path);
+            Err(format!("ffmpeg failed, {}: {}", status, stderr_tail))
+        } else {
+            let verified = verify(&out_path, &expected);
+            if let Err(reason) = &verified {
+                quarantine(&out_path, reason);
+            }
+            verified
+        };
+        let transcoded = match verified {
+            Ok(transcoded) => transcoded,
+            Err(reason) => {
+                error!("Keeping {}, its transcode failed: {}", movie_path, reason);
+                self.events.publish(RecorderEvent::ArchiveTranscodeFailed {
+                    movie: filename,
+                    reason,
+                });
+                return Ok(());
+            }
+        };
+
+        let bytes_before = fs::metadata(&movie_path).map(|m| m.len()).unwrap_or(0);
+        let bytes_after = fs::metadata(&out_path).map(|m| m.len()).unwrap_or(0);
+        let codec = transcoded.codec;
+        // the encoding folder is on the movies disk, the rename replaces the movie atomically
+        info!("Replacing {} with its transcode", movie_path);
+        fs::rename(&out_path, &movie_path)
+            .map_err(|e| format!("Error moving {} to {}: {}", out_path, movie_path, e))?;
+        metrics::add(&metrics::BYTES_WRITTEN, bytes_after);
+        let mut metadata = MovieMetadata::read(&metadata_path(movie));
+        metadata.transcoded = Some(Transcoded {
+            from_codec: original.codec,
+            codec: codec.clone(),
+            profile: profile_name,
+            bytes_before,
+            bytes_after,
+            transcoded_at: Local::now().timestamp(),
+        });
+        metadata.write(&metadata_path(movie));
+        info!(
+            "Transcoded {} from {} to {} bytes",
+            movie_path, bytes_before, bytes_after
+        );
+        self.events.publish(RecorderEvent::ArchiveTranscoded {
+            movie: filename,
+            codec: codec.unwrap_or_default(),
+            bytes_before,
+            bytes_after,
+        });
+        Ok(())
+    }
+}
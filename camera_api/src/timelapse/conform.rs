@@ -0,0 +1,183 @@
+use crate::config::EncoderProfile;
+use crate::timelapse::encoder::{read_tail, FRAMERATE};
+use crate::timelapse::probe::{probe, probed_codec, verify, Expected, Probe};
+use crate::timelapse::{JobRunner, Movie, ENCODING_FOLDER};
+use log::{error, info};
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// What the concat demuxer needs to be the same in every clip to copy them
+#[derive(Clone, Debug, PartialEq)]
+struct ClipFormat {
+    codec: Option<String>,
+    width: Option<u64>,
+    height: Option<u64>,
+}
+
+impl ClipFormat {
+    fn of(probe: &Probe) -> Self {
+        Self {
+            codec: probe.codec.clone(),
+            width: probe.width,
+            height: probe.height,
+        }
+    }
+}
+
+/// Hourly clips ready to be concatenated, all in the same format
+pub struct ConformedClips {
+    /// ffmpeg concat demuxer list of the clips, in order
+    pub list_path: String,
+    /// what the day movie must look like
+    pub expected: Expected,
+}
+
+/// Folder of a stitch job, holding its list file and the re-encoded clips
+pub fn stitch_work_dir(day: i64) -> String {
+    format!("{}/{}.stitch", ENCODING_FOLDER, day)
+}
+
+/// The format most clips are in, the first one seen on ties
+fn majority_format(formats: &[ClipFormat]) -> ClipFormat {
+    let mut counts: Vec<(&ClipFormat, usize)> = vec![];
+    for format in formats {
+        match counts.iter_mut().find(|(counted, _)| *counted == format) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((format, 1)),
+        }
+    }
+    let mut majority = counts[0];
+    for count in counts {
+        if count.1 > majority.1 {
+            majority = count;
+        }
+    }
+    majority.0.clone()
+}
+
+impl JobRunner {
+    /// Profile encoding to the codec: the configured one when it does, a software one otherwise,
+    /// the hardware encoders being the least reliable
+    fn profile_for_codec(&self, codec: &str) -> Option<EncoderProfile> {
+        let encodes_codec =
+            |profile: &&EncoderProfile| probed_codec(&profile.codec).as_deref() == Some(codec);
+        let profiles = &self.config.encoder_profiles;
+        self.config
+            .profile(&self.config.encoder_profile)
+            .filter(encodes_codec)
+            .or_else(|| {
+                profiles
+                    .values()
+                    .filter(encodes_codec)
+                    .find(|profile| profile.codec.starts_with("lib"))
+            })
+            .or_else(|| profiles.values().find(encodes_codec))
+            .cloned()
+    }
+
+    /// Re-encodes a clip to the format, in the work dir
+    fn conform_clip(
+        &self,
+        clip: &Movie,
+        clip_probe: &Probe,
+        format: &ClipFormat,
+        work_dir: &str,
+    ) -> Result<String, String> {
+        let codec = format
+            .codec
+            .as_deref()
+            .ok_or_else(|| "the clips have no video stream".to_string())?;
+        let profile = self
+            .profile_for_codec(codec)
+            .ok_or_else(|| format!("no encoder profile encodes {}", codec))?;
+        let (width, height) = match (format.width, format.height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err("the clips have no resolution".to_string()),
+        };
+        let out_path = format!("{}/{}", work_dir, clip.filename);
+        info!(
+            "Re-encoding {} to {} {}x{} for stitching",
+            clip.path, codec, width, height
+        );
+        let mut process = Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(&clip.path)
+            // keeps the capture time subtitles
+            .arg("-map")
+            .arg("0")
+            .arg("-c")
+            .arg("copy")
+            .arg("-vf")
+            .arg(format!("scale={}:{},fps={}", width, height, FRAMERATE))
+            // the fastest preset, the stitch runs while the next day is recorded
+            .args(profile.ffmpeg_args(profile.presets.last().map(String::as_str)))
+            .arg("-nostats")
+            .arg(&out_path)
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("command failed to start");
+        let stderr_tail = read_tail(process.stderr.take().expect("ffmpeg stderr is piped"));
+        let status = process
+            .wait()
+            .expect("Error while waiting for re-encoding process!");
+        let stderr_tail = stderr_tail.join().unwrap_or_default();
+        if !status.success() {
+            error!("{}", stderr_tail);
+            return Err(format!("re-encoding {} failed, {}", clip.path, status));
+        }
+        verify(
+            &out_path,
+            &Expected {
+                duration_secs: Some(clip_probe.duration_secs),
+                codec: format.codec.clone(),
+                resolution: Some((width, height)),
+            },
+        )?;
+        Ok(out_path)
+    }
+
+    /// Probes the hourly clips and re-encodes those not in the format of most of them, which
+    /// happens when the camera or encoder settings change during the day, so the concat demuxer
+    /// can copy them all
+    pub(super) fn conform_clips(
+        &self,
+        day: i64,
+        movies: &[Movie],
+    ) -> Result<ConformedClips, String> {
+        let probes = movies
+            .iter()
+            .map(|movie| {
+                probe(&movie.path).ok_or_else(|| format!("{} cannot be probed", movie.path))
+            })
+            .collect::<Result<Vec<Probe>, String>>()?;
+        let formats: Vec<ClipFormat> = probes.iter().map(ClipFormat::of).collect();
+        let majority = majority_format(&formats);
+        let work_dir = stitch_work_dir(day);
+        fs::create_dir_all(&work_dir).expect(&format!("Error creating {}", work_dir));
+
+        let mut list = String::new();
+        for ((movie, probe), format) in movies.iter().zip(&probes).zip(&formats) {
+            let path = if *format == majority {
+                movie.path.clone()
+            } else {
+                info!(
+                    "{} is {:?} while most clips are {:?}",
+                    movie.path, format, majority
+                );
+                self.conform_clip(movie, probe, &majority, &work_dir)?
+            };
+            list.push_str(&format!("file \'{}\'\n", path));
+        }
+        let list_path = format!("{}/files.txt", work_dir);
+        fs::write(&list_path, list).expect(&format!("Error writing {}", list_path));
+        Ok(ConformedClips {
+            list_path,
+            expected: Expected {
+                duration_secs: Some(probes.iter().map(|probe| probe.duration_secs).sum()),
+                codec: majority.codec.clone(),
+                resolution: majority.width.zip(majority.height),
+            },
+        })
+    }
+}
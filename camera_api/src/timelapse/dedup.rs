@@ -0,0 +1,251 @@
+use crate::config::FrameDedupConfig;
+use crate::timelapse::metadata::FrameCounts;
+use jpeg_decoder::{Decoder, PixelFormat};
+use log::error;
+use std::fs;
+
+/// File in a pics folder where the picture taking thread writes how many frames it kept and
+/// dropped, copied to the movie metadata once encoded
+const FRAME_COUNTS_FILENAME: &str = "frame_counts.json";
+
+/// Downscaled luma of a frame, what frames are compared on
+#[derive(Clone)]
+pub struct Luma {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Luma {
+    /// None if the picture cannot be decoded
+    pub fn of(jpeg: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(jpeg);
+        // the IDCT scales down to 1/8 at most, 205x154 at the camera resolution, so most of the
+        // decoding is skipped
+        let decoded = decoder
+            .scale(1, 1)
+            .and_then(|size| decoder.decode().map(|pixels| (size, pixels)));
+        let ((width, height), pixels) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                error!("Error decoding picture: {}", e);
+                return None;
+            }
+        };
+        let pixels = match decoder.info()?.pixel_format {
+            PixelFormat::L8 => pixels,
+            PixelFormat::RGB24 => pixels
+                .chunks(3)
+                .map(|rgb| {
+                    ((77 * rgb[0] as u32 + 150 * rgb[1] as u32 + 29 * rgb[2] as u32) >> 8) as u8
+                })
+                .collect(),
+            PixelFormat::CMYK32 => return None,
+        };
+        Some(Self {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<u8>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Percentage of the pixels whose luma moved by more than the threshold, the top rows
+    /// excepted. 100 if the frames are not the same size.
+    pub fn changed_percent(
+        &self,
+        other: &Luma,
+        pixel_threshold: u8,
+        ignore_top_percent: u32,
+    ) -> f64 {
+        if self.width != other.width || self.height != other.height {
+            return 100.;
+        }
+        let skipped = self.width * (self.height * ignore_top_percent as usize / 100);
+        let compared = self.pixels.len().saturating_sub(skipped);
+        if compared == 0 {
+            return 0.;
+        }
+        let changed = self.pixels[skipped..]
+            .iter()
+            .zip(&other.pixels[skipped..])
+            .filter(|(a, b)| (**a as i16 - **b as i16).abs() > pixel_threshold as i16)
+            .count();
+        changed as f64 * 100. / compared as f64
+    }
+}
+
+/// Decides which frames of a segment are kept, comparing each to the last kept one
+pub struct FrameFilter {
+    config: FrameDedupConfig,
+    last_kept: Option<Luma>,
+    /// frames dropped since the last kept one
+    dropped_in_a_row: u32,
+    pub counts: FrameCounts,
+}
+
+impl FrameFilter {
+    pub fn new(config: FrameDedupConfig) -> Self {
+        Self {
+            config,
+            last_kept: None,
+            dropped_in_a_row: 0,
+            counts: FrameCounts::default(),
+        }
+    }
+
+    /// Whether it needs the luma of the frames
+    pub fn compares(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Whether the frame is kept: the first one is, and those that could not be decoded
+    pub fn keep(&mut self, luma: Option<&Luma>) -> bool {
+        if !self.config.enabled {
+            self.counts.kept += 1;
+            return true;
+        }
+        let similar = match (luma, &self.last_kept) {
+            (Some(luma), Some(last_kept)) => {
+                luma.changed_percent(
+                    last_kept,
+                    self.config.pixel_threshold,
+                    self.config.ignore_top_percent,
+                ) <= self.config.changed_percent
+            }
+            _ => false,
+        };
+        let decimated =
+            self.config.keep_every > 0 && self.dropped_in_a_row + 1 >= self.config.keep_every;
+        if similar && !decimated {
+            self.dropped_in_a_row += 1;
+            self.counts.dropped += 1;
+            return false;
+        }
+        // slow changes, like the dawn, are followed by comparing to the latest kept frame
+        self.last_kept = luma.cloned();
+        self.dropped_in_a_row = 0;
+        self.counts.kept += 1;
+        true
+    }
+}
+
+pub fn write_frame_counts(pics_folder_path: &str, counts: &FrameCounts) {
+    let path = format!("{}/{}", pics_folder_path, FRAME_COUNTS_FILENAME);
+    let json = serde_json::to_string(counts).expect("Error serializing frame counts");
+    if let Err(e) = fs::write(&path, json) {
+        error!("Error writing {}: {}", path, e);
+    }
+}
+
+/// None if the segment was interrupted before its counts were written
+pub fn read_frame_counts(pics_folder_path: &str) -> Option<FrameCounts> {
+    let path = format!("{}/{}", pics_folder_path, FRAME_COUNTS_FILENAME);
+    let counts = fs::read_to_string(&path).ok()?;
+    serde_json::from_str(&counts)
+        .map_err(|e| error!("Invalid frame counts {}: {}", path, e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uniform(width: usize, height: usize, value: u8) -> Luma {
+        Luma::from_pixels(width, height, vec![value; width * height])
+    }
+
+    fn filter(keep_every: u32) -> FrameFilter {
+        FrameFilter::new(FrameDedupConfig {
+            enabled: true,
+            pixel_threshold: 20,
+            changed_percent: 1.0,
+            keep_every,
+            ignore_top_percent: 0,
+        })
+    }
+
+    #[test]
+    fn identical_frames_are_dropped() {
+        let mut filter = filter(0);
+        let frame = uniform(10, 10, 100);
+        assert!(filter.keep(Some(&frame)));
+        assert!(!filter.keep(Some(&frame)));
+        // changes under the pixel threshold are noise
+        assert!(!filter.keep(Some(&uniform(10, 10, 115))));
+        assert_eq!((filter.counts.kept, filter.counts.dropped), (1, 2));
+    }
+
+    #[test]
+    fn changed_frames_are_kept() {
+        let mut filter = filter(0);
+        assert!(filter.keep(Some(&uniform(10, 10, 100))));
+        let mut changed = uniform(10, 10, 100);
+        // 2% of the pixels, over the 1% allowed
+        changed.pixels[50] = 200;
+        changed.pixels[51] = 0;
+        assert!(filter.keep(Some(&changed)));
+        // compared to the last kept frame, which is now the changed one
+        assert!(!filter.keep(Some(&changed)));
+        // frames that cannot be decoded are kept
+        assert!(filter.keep(None));
+        assert_eq!((filter.counts.kept, filter.counts.dropped), (3, 1));
+    }
+
+    #[test]
+    fn one_frame_of_keep_every_is_kept() {
+        let mut filter = filter(3);
+        let frame = uniform(10, 10, 100);
+        let kept: Vec<bool> = (0..7).map(|_| filter.keep(Some(&frame))).collect();
+        assert_eq!(kept, vec![true, false, false, true, false, false, true]);
+        assert_eq!((filter.counts.kept, filter.counts.dropped), (3, 4));
+    }
+
+    #[test]
+    fn disabled_filter_keeps_everything() {
+        let mut filter = FrameFilter::new(FrameDedupConfig {
+            enabled: false,
+            ..filter(0).config
+        });
+        let frame = uniform(10, 10, 100);
+        assert!(!filter.compares());
+        assert!(filter.keep(Some(&frame)));
+        assert!(filter.keep(Some(&frame)));
+        assert_eq!((filter.counts.kept, filter.counts.dropped), (2, 0));
+    }
+
+    #[test]
+    fn top_rows_are_ignored() {
+        let still = uniform(10, 10, 100);
+        let mut annotated = uniform(10, 10, 100);
+        // the first row, where the time is annotated, changes
+        for pixel in &mut annotated.pixels[..10] {
+            *pixel = 255;
+        }
+        assert_eq!(annotated.changed_percent(&still, 20, 0), 10.);
+        assert_eq!(annotated.changed_percent(&still, 20, 10), 0.);
+        // of the 80 pixels left below the 2 ignored rows
+        annotated.pixels[20] = 255;
+        assert_eq!(annotated.changed_percent(&still, 20, 20), 1.25);
+        assert_eq!(annotated.changed_percent(&still, 20, 100), 0.);
+    }
+
+    #[test]
+    fn frames_of_another_size_are_all_changed() {
+        let frame = uniform(10, 10, 100);
+        assert_eq!(frame.changed_percent(&uniform(10, 5, 100), 20, 0), 100.);
+        assert_eq!(frame.changed_percent(&uniform(5, 20, 100), 20, 0), 100.);
+        let mut filter = filter(0);
+        assert!(filter.keep(Some(&frame)));
+        assert!(filter.keep(Some(&uniform(10, 5, 100))));
+    }
+}
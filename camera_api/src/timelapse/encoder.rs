@@ -1,40 +1,269 @@
-use crate::timelapse::{EncodingMessage, EncodingOutput, TimeLapseManufacturer};
-use log::info;
-use std::process::Command;
-impl TimeLapseManufacturer {
+use crate::events::RecorderEvent;
+use crate::metrics;
+use crate::timelapse::frames::{frame_index_filename, read_frame_log, FrameIndex};
+#[cfg(feature = "native-encoder")]
+use crate::timelapse::native;
+use crate::timelapse::probe::{quarantine, verify, Expected};
+use crate::timelapse::subtitles::write_capture_time_srt;
+use crate::timelapse::thumbnails::generate_thumbnails;
+use crate::timelapse::{
+    EncodingFailure, EncodingMessage, EncodingOutput, FfmpegProgress, JobRunner,
+};
+use log::{error, info};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// Frames per second of the encoded movies
+pub const FRAMERATE: u32 = 10;
+/// Resolution raspistill takes the pictures at
+pub const RESOLUTION: (u64, u64) = (1640, 1232);
+/// Lines of ffmpeg output kept to explain a failure
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Reads the output of a process from a thread, so it never blocks on a full pipe, keeping the
+/// last lines
+pub(super) fn read_tail<R: Read + Send + 'static>(output: R) -> JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+        for line in BufReader::new(output).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        tail.into_iter().collect::<Vec<String>>().join("\n")
+    })
+}
+
+/// Parses an out_time of ffmpeg -progress, 00:01:02.500000
+fn parse_out_time(out_time: &str) -> Option<i64> {
+    let mut parts = out_time.trim_start_matches('-').split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some((hours * 3600 + minutes * 60) * 1000 + (seconds * 1000.0) as i64)
+}
+
+/// Reads the key=value blocks ffmpeg -progress writes, each ending with a progress= line
+fn send_progress<R: Read>(output: R, sender: &crossbeam_channel::Sender<EncodingMessage>) {
+    let mut progress = FfmpegProgress::default();
+    for line in BufReader::new(output).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let mut key_value = line.splitn(2, '=');
+        let (key, value) = match (key_value.next(), key_value.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => continue,
+        };
+        match key {
+            "frame" => progress.frame = value.parse().unwrap_or(progress.frame),
+            "fps" => progress.fps = value.parse().unwrap_or(progress.fps),
+            "out_time" => progress.out_time_millis = parse_out_time(value).unwrap_or(0),
+            // N/A until the first frame is encoded
+            "speed" => progress.speed = value.trim_end_matches('x').parse().ok(),
+            "progress" => {
+                let _ = sender.send(EncodingMessage::Progress(progress.clone()));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Muxes the pictures without ffmpeg, so the movie has no capture time subtitles nor thumbnails
+#[cfg(feature = "native-encoder")]
+fn encode_natively(
+    img_dir: &str,
+    output_dir: &str,
+    movie_stem: &str,
+    capture_times: &[chrono::DateTime<chrono::Local>],
+    encoding_output: EncodingOutput,
+    sender: &crossbeam_channel::Sender<EncodingMessage>,
+) -> Result<(), crossbeam_channel::SendError<EncodingMessage>> {
+    let started_at = Instant::now();
+    let encoded = native::encode_pictures(
+        img_dir,
+        &encoding_output.output_path_with_filename,
+        FRAMERATE,
+    );
+    metrics::inc(&metrics::ENCODES);
+    metrics::add_duration(&metrics::ENCODE_MILLIS, started_at.elapsed());
+    match encoded {
+        Ok(frames) => {
+            if let Ok(movie) = fs::metadata(&encoding_output.output_path_with_filename) {
+                metrics::add(&metrics::BYTES_WRITTEN, movie.len());
+            }
+            if let Err(reason) = native::verify(&encoding_output.output_path_with_filename, frames)
+            {
+                error!("Encoded movie is invalid: {}", reason);
+                metrics::inc(&metrics::ENCODE_FAILURES);
+                quarantine(&encoding_output.output_path_with_filename, &reason);
+                return sender.send(EncodingMessage::Failed(EncodingFailure {
+                    exit_code: None,
+                    stderr_tail: reason,
+                }));
+            }
+            if !capture_times.is_empty() {
+                FrameIndex::new(capture_times).write(&format!(
+                    "{}/{}",
+                    output_dir,
+                    frame_index_filename(movie_stem)
+                ));
+            }
+            info!("Native encoding done!");
+            sender.send(EncodingMessage::Done(encoding_output))
+        }
+        Err(e) => {
+            error!("Native encoding failed: {}", e);
+            metrics::inc(&metrics::ENCODE_FAILURES);
+            let _ = fs::remove_file(&encoding_output.output_path_with_filename);
+            sender.send(EncodingMessage::Failed(EncodingFailure {
+                exit_code: None,
+                stderr_tail: e.to_string(),
+            }))
+        }
+    }
+}
+
+impl JobRunner {
     pub fn start_encoding_thread(
         &mut self,
         img_dir: String,
         output_path_with_filename: String,
         filename: String,
+        encoder_args: Vec<String>,
+        expected: Expected,
     ) {
         info!("Starting encoding thread");
         let (sender, receiver) = crossbeam_channel::bounded::<EncodingMessage>(2);
         self.encoding_thread = Some(receiver);
+        let events = self.events.clone();
+        events.publish(RecorderEvent::EncodeStarted {
+            movie: filename.clone(),
+        });
         std::thread::spawn(move || {
+            let output_dir = Path::new(&output_path_with_filename)
+                .parent()
+                .expect("Encoding output has no parent dir")
+                .to_string_lossy()
+                .to_string();
+            let movie_stem = filename.replace(".mp4", "");
+            // subtitle track with the real capture time of each frame
+            let capture_times = read_frame_log(&img_dir);
+            #[cfg(feature = "native-encoder")]
+            {
+                if native::ffmpeg_missing() {
+                    let encoding_output = EncodingOutput {
+                        output_path_with_filename,
+                        filename,
+                    };
+                    return encode_natively(
+                        &img_dir,
+                        &output_dir,
+                        &movie_stem,
+                        &capture_times,
+                        encoding_output,
+                        &sender,
+                    );
+                }
+            }
+            let srt_path = format!("{}/{}.srt", output_dir, movie_stem);
+            if !capture_times.is_empty() {
+                write_capture_time_srt(&capture_times, &srt_path);
+            }
             // ffmpeg -framerate 10 -i %05d.jpg -video_size 1640:1232 -vf fps=10 -b:v 1.2M test.mp4
             //ffmpeg -framerate 10 -i ./a/%05d.jpg -video_size 1640:1232 -preset fast -vf fps=10 -crf 35 /home/pi/test_crf_35.mp4
-            let mut process = Command::new("ffmpeg")
-                //-i image%04d.jpg -video_size 1640:1232 -c:v h264_omx -b:v 1.2M -vf fps=10 out.mp4
+            let mut command = Command::new("ffmpeg");
+            command
                 .arg("-framerate")
-                .arg("10")
+                .arg(format!("{}", FRAMERATE))
                 .arg("-i")
-                .arg(format!("{}/%05d.jpg", img_dir))
+                .arg(format!("{}/%05d.jpg", img_dir));
+            if !capture_times.is_empty() {
+                command
+                    .arg("-i")
+                    .arg(&srt_path)
+                    .arg("-map")
+                    .arg("0:v")
+                    .arg("-map")
+                    .arg("1:s")
+                    .arg("-c:s")
+                    .arg("mov_text")
+                    .arg("-metadata:s:s:0")
+                    .arg("title=Capture time");
+            }
+            let started_at = Instant::now();
+            let mut process = command
                 .arg("-video_size")
-                .arg("1640:1232")
+                .arg(format!("{}:{}", RESOLUTION.0, RESOLUTION.1))
                 .arg("-vf")
-                .arg("fps=10")
-                .arg("-preset")
-                .arg("slow")
-                .arg("-crf")
-                .arg("32")
+                .arg(format!("fps={}", FRAMERATE))
+                .args(encoder_args)
+                // no progress line, keeps the stderr tail readable
+                .arg("-nostats")
+                .arg("-progress")
+                .arg("pipe:1")
                 .arg(output_path_with_filename.clone())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
                 .spawn()
                 .expect("command failed to start");
             info!("Started encoding process!");
-            process
+            let stderr_tail = read_tail(process.stderr.take().expect("ffmpeg stderr is piped"));
+            send_progress(
+                process.stdout.take().expect("ffmpeg stdout is piped"),
+                &sender,
+            );
+            let status = process
                 .wait()
                 .expect("Error while waiting for encoding process!");
+            let stderr_tail = stderr_tail.join().unwrap_or_default();
+            metrics::inc(&metrics::ENCODES);
+            metrics::add_duration(&metrics::ENCODE_MILLIS, started_at.elapsed());
+            if let Ok(movie) = fs::metadata(&output_path_with_filename) {
+                metrics::add(&metrics::BYTES_WRITTEN, movie.len());
+            }
+            if !capture_times.is_empty() {
+                fs::remove_file(&srt_path).expect(&format!("Error removing {}", srt_path));
+            }
+            if !status.success() {
+                error!("Encoding process failed: {}", status);
+                error!("{}", stderr_tail);
+                metrics::inc(&metrics::ENCODE_FAILURES);
+                // the movie may be missing or truncated
+                let _ = fs::remove_file(&output_path_with_filename);
+                return sender.send(EncodingMessage::Failed(EncodingFailure {
+                    exit_code: status.code(),
+                    stderr_tail,
+                }));
+            }
+            if let Err(reason) = verify(&output_path_with_filename, &expected) {
+                error!("Encoded movie is invalid: {}", reason);
+                metrics::inc(&metrics::ENCODE_FAILURES);
+                quarantine(&output_path_with_filename, &reason);
+                return sender.send(EncodingMessage::Failed(EncodingFailure {
+                    exit_code: status.code(),
+                    stderr_tail: reason,
+                }));
+            }
+            if !capture_times.is_empty() {
+                FrameIndex::new(&capture_times).write(&format!(
+                    "{}/{}",
+                    output_dir,
+                    frame_index_filename(&movie_stem)
+                ));
+            }
+            generate_thumbnails(&img_dir, &output_dir, &movie_stem);
             let encoding_output = EncodingOutput {
                 output_path_with_filename,
                 filename,
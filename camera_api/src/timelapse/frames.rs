@@ -0,0 +1,168 @@
+use crate::timelapse::encoder::FRAMERATE;
+use chrono::prelude::*;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+/// File in a pics folder where the picture taking thread records when each frame was captured,
+/// one line per frame: "<frame number> <unix timestamp in milliseconds>"
+const FRAME_LOG_FILENAME: &str = "frames.log";
+
+pub struct FrameLog {
+    file: File,
+}
+
+impl FrameLog {
+    pub fn create(pics_folder_path: &str) -> Self {
+        let path = format!("{}/{}", pics_folder_path, FRAME_LOG_FILENAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .expect(&format!("Error creating frame log at {}", path));
+        Self { file }
+    }
+
+    pub fn record(&mut self, frame: u32, captured_at: DateTime<Local>) {
+        writeln!(self.file, "{} {}", frame, captured_at.timestamp_millis())
+            .expect("Error writing to frame log");
+    }
+}
+
+/// Capture time of each frame in the pics folder, indexed by frame number.
+/// Empty if the folder has no frame log.
+pub fn read_frame_log(pics_folder_path: &str) -> Vec<DateTime<Local>> {
+    let path = format!("{}/{}", pics_folder_path, FRAME_LOG_FILENAME);
+    match fs::read_to_string(&path) {
+        Ok(log) => parse_frame_log(&log, &path),
+        Err(e) => {
+            error!("Could not read frame log {}: {}", path, e);
+            vec![]
+        }
+    }
+}
+
+/// Stops at the first invalid line or out of order frame, the frames after it have no time
+fn parse_frame_log(log: &str, path: &str) -> Vec<DateTime<Local>> {
+    let mut capture_times = vec![];
+    for line in log.lines() {
+        let mut fields = line.split_whitespace();
+        let frame = fields.next().and_then(|f| f.parse::<usize>().ok());
+        let millis = fields.next().and_then(|m| m.parse::<i64>().ok());
+        match (frame, millis) {
+            (Some(frame), Some(millis)) if frame == capture_times.len() => {
+                capture_times.push(Local.timestamp_millis(millis));
+            }
+            _ => {
+                error!("Invalid frame log line in {}: {}", path, line);
+                break;
+            }
+        }
+    }
+    capture_times
+}
+
+/// Maps each frame of a movie to when it was captured, so playback positions can be turned
+/// into wall-clock times and back. Stored next to the movie: 1234.mp4 -> 1234.frames.json
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FrameIndex {
+    fps: u32,
+    /// capture time of each frame, as unix timestamps in milliseconds
+    capture_times: Vec<i64>,
+}
+
+pub fn frame_index_filename(movie_stem: &str) -> String {
+    format!("{}.frames.json", movie_stem)
+}
+
+impl FrameIndex {
+    pub fn new(capture_times: &[DateTime<Local>]) -> Self {
+        Self {
+            fps: FRAMERATE,
+            capture_times: capture_times.iter().map(|t| t.timestamp_millis()).collect(),
+        }
+    }
+
+    pub fn read(path: &str) -> Option<Self> {
+        let index = fs::read_to_string(path)
+            .map_err(|e| error!("Could not read frame index {}: {}", path, e))
+            .ok()?;
+        serde_json::from_str(&index)
+            .map_err(|e| error!("Invalid frame index {}: {}", path, e))
+            .ok()
+    }
+
+    pub fn write(&self, path: &str) {
+        let index = serde_json::to_string(self).expect("Error serializing frame index");
+        fs::write(path, index).expect(&format!("Error writing frame index {}", path));
+    }
+
+    /// Index of the movie made by concatenating the movies of the given indexes, in order
+    pub fn concat(indexes: Vec<FrameIndex>) -> Self {
+        Self {
+            fps: FRAMERATE,
+            capture_times: indexes
+                .into_iter()
+                .flat_map(|index| index.capture_times)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(capture_times: &[DateTime<Local>]) -> Vec<i64> {
+        capture_times.iter().map(|t| t.timestamp_millis()).collect()
+    }
+
+    #[test]
+    fn frame_log_lines_are_parsed() {
+        let log = "0 1614861000000\n1 1614861000500\n2 1614861001000\n";
+        assert_eq!(
+            millis(&parse_frame_log(log, "frames.log")),
+            vec![1614861000000, 1614861000500, 1614861001000]
+        );
+    }
+
+    #[test]
+    fn frame_log_stops_at_the_first_invalid_line() {
+        let log = "0 1614861000000\n1 garbage\n2 1614861001000\n";
+        assert_eq!(
+            millis(&parse_frame_log(log, "frames.log")),
+            vec![1614861000000]
+        );
+        let skipped_frame = "0 1614861000000\n2 1614861001000\n";
+        assert_eq!(
+            millis(&parse_frame_log(skipped_frame, "frames.log")),
+            vec![1614861000000]
+        );
+        assert!(parse_frame_log("", "frames.log").is_empty());
+    }
+
+    #[test]
+    fn recorded_frames_are_read_back() {
+        let dir = std::env::temp_dir().join(format!("frame_log_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir_path = dir.to_string_lossy().to_string();
+        let first = Local.timestamp_millis(1614861000000);
+        let mut frame_log = FrameLog::create(&dir_path);
+        frame_log.record(0, first);
+        frame_log.record(1, first + chrono::Duration::milliseconds(1500));
+        let capture_times = read_frame_log(&dir_path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(millis(&capture_times), vec![1614861000000, 1614861001500]);
+    }
+
+    #[test]
+    fn concatenated_indexes_keep_the_order() {
+        let first = FrameIndex::new(&[Local.timestamp_millis(1), Local.timestamp_millis(2)]);
+        let second = FrameIndex::new(&[Local.timestamp_millis(3)]);
+        let day = FrameIndex::concat(vec![first, second]);
+        assert_eq!(day.fps, FRAMERATE);
+        assert_eq!(day.capture_times, vec![1, 2, 3]);
+    }
+}
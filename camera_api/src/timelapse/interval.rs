@@ -0,0 +1,175 @@
+use crate::config::{CaptureIntervalConfig, FrameDedupConfig};
+use crate::timelapse::dedup::Luma;
+use std::time::Duration;
+
+/// Picks the interval to the next capture from the motion between the last two frames: the
+/// shortest one as soon as there is motion, growing back to the longest one once the scene stays
+/// still
+pub struct CaptureScheduler {
+    config: CaptureIntervalConfig,
+    /// as set for frame_dedup
+    pixel_threshold: u8,
+    ignore_top_percent: u32,
+    previous: Option<Luma>,
+    /// consecutive frames below still_percent
+    still_in_a_row: u32,
+    interval: Duration,
+}
+
+impl CaptureScheduler {
+    pub fn new(config: CaptureIntervalConfig, frame_dedup: &FrameDedupConfig) -> Self {
+        // starts fast, the scene is unknown
+        let interval = Duration::from_millis(config.min_interval_millis);
+        Self {
+            config,
+            pixel_threshold: frame_dedup.pixel_threshold,
+            ignore_top_percent: frame_dedup.ignore_top_percent,
+            previous: None,
+            still_in_a_row: 0,
+            interval,
+        }
+    }
+
+    /// Whether it needs the luma of the frames
+    pub fn adapts(&self) -> bool {
+        self.config.adaptive
+    }
+
+    /// Interval between the capture of the frame and the next one. Frames that cannot be decoded
+    /// leave it unchanged.
+    pub fn next_interval(&mut self, luma: Option<Luma>) -> Duration {
+        if !self.config.adaptive {
+            return Duration::from_millis(self.config.min_interval_millis);
+        }
+        let changed_percent = match (&luma, &self.previous) {
+            (Some(luma), Some(previous)) => {
+                Some(luma.changed_percent(previous, self.pixel_threshold, self.ignore_top_percent))
+            }
+            _ => None,
+        };
+        self.previous = luma;
+        let changed_percent = match changed_percent {
+            Some(changed_percent) => changed_percent,
+            None => return self.interval,
+        };
+        // between the two thresholds the interval is kept, so noise around one of them does not
+        // make the rate flap
+        if changed_percent > self.config.motion_percent {
+            self.still_in_a_row = 0;
+            self.interval = Duration::from_millis(self.config.min_interval_millis);
+        } else if changed_percent <= self.config.still_percent {
+            self.still_in_a_row += 1;
+            if self.still_in_a_row >= self.config.still_frames {
+                let max_interval = Duration::from_millis(self.config.max_interval_millis);
+                // doubling, with at least a second, reaches the longest interval in a few frames
+                self.interval = (self.interval * 2)
+                    .max(Duration::from_secs(1))
+                    .min(max_interval);
+            }
+        } else {
+            self.still_in_a_row = 0;
+        }
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(adaptive: bool) -> CaptureScheduler {
+        let config = CaptureIntervalConfig {
+            adaptive,
+            min_interval_millis: 100,
+            max_interval_millis: 10000,
+            motion_percent: 2.0,
+            still_percent: 0.5,
+            still_frames: 3,
+        };
+        let frame_dedup = FrameDedupConfig {
+            ignore_top_percent: 0,
+            ..FrameDedupConfig::default()
+        };
+        CaptureScheduler::new(config, &frame_dedup)
+    }
+
+    /// Frame of 1000 pixels, the first ones of which are bright
+    fn frame(bright: usize) -> Option<Luma> {
+        let pixels = (0..1000)
+            .map(|i| if i < bright { 200 } else { 100 })
+            .collect();
+        Some(Luma::from_pixels(100, 10, pixels))
+    }
+
+    fn millis(intervals: &[Duration]) -> Vec<u128> {
+        intervals
+            .iter()
+            .map(|interval| interval.as_millis())
+            .collect()
+    }
+
+    #[test]
+    fn interval_ramps_up_while_still() {
+        let mut scheduler = scheduler(true);
+        let intervals: Vec<Duration> = (0..10).map(|_| scheduler.next_interval(frame(0))).collect();
+        // the first frame has nothing to be compared to, then 3 still frames are needed, and
+        // doubling starts from a second
+        assert_eq!(
+            millis(&intervals),
+            vec![100, 100, 100, 1000, 2000, 4000, 8000, 10000, 10000, 10000]
+        );
+    }
+
+    #[test]
+    fn motion_resets_the_interval() {
+        let mut scheduler = scheduler(true);
+        for _ in 0..6 {
+            scheduler.next_interval(frame(0));
+        }
+        // 3% of the pixels changed
+        assert_eq!(scheduler.next_interval(frame(30)).as_millis(), 100);
+        let intervals: Vec<Duration> = (0..4).map(|_| scheduler.next_interval(frame(30))).collect();
+        assert_eq!(millis(&intervals), vec![100, 100, 1000, 2000]);
+    }
+
+    #[test]
+    fn interval_holds_between_the_thresholds() {
+        let mut scheduler = scheduler(true);
+        for _ in 0..5 {
+            scheduler.next_interval(frame(0));
+        }
+        // each of the 4 first frames has 1% of its pixels changed from the previous one, neither
+        // motion nor still
+        let intervals: Vec<Duration> = [10, 0, 10, 0, 0, 0, 0]
+            .iter()
+            .map(|bright| scheduler.next_interval(frame(*bright)))
+            .collect();
+        // the still frames in a row start over after each change
+        assert_eq!(
+            millis(&intervals),
+            vec![2000, 2000, 2000, 2000, 2000, 2000, 4000]
+        );
+    }
+
+    #[test]
+    fn undecoded_frames_keep_the_interval() {
+        let mut scheduler = scheduler(true);
+        for _ in 0..4 {
+            scheduler.next_interval(frame(0));
+        }
+        assert_eq!(scheduler.next_interval(None).as_millis(), 1000);
+        // the next frame has nothing to be compared to
+        assert_eq!(scheduler.next_interval(frame(500)).as_millis(), 1000);
+        assert_eq!(scheduler.next_interval(frame(0)).as_millis(), 100);
+    }
+
+    #[test]
+    fn fixed_interval_when_not_adaptive() {
+        let mut scheduler = scheduler(false);
+        assert!(!scheduler.adapts());
+        for bright in &[0, 0, 0, 0, 0, 500, 10, 0] {
+            assert_eq!(scheduler.next_interval(frame(*bright)).as_millis(), 100);
+        }
+        assert_eq!(scheduler.next_interval(None).as_millis(), 100);
+    }
+}
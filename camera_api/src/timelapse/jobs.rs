@@ -0,0 +1,165 @@
+use chrono::prelude::*;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Pending jobs are persisted here, next to the pictures they refer to, so they are resumed
+/// after a restart
+const JOBS_FILE: &str = "/mnt/skynet/jobs.json";
+/// Jobs that failed are moved here instead of being run again on every start
+const FAILED_JOBS_FILE: &str = "/mnt/skynet/failed_jobs.json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Job {
+    /// encodes the pictures of a segment folder into an hourly clip of the today folder
+    Encode {
+        /// timestamp the segment started at, which names its folder and the clip
+        segment: i64,
+        /// encoder profile of the config, its encoder_profile if none
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// rolls the hourly clips of the today folder up into the day movie
+    Stitch,
+}
+
+/// Written to FAILED_JOBS_FILE, for a manual retry
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailedJob {
+    pub job: Job,
+    pub reason: String,
+    pub failed_at: i64,
+}
+
+/// First in first out queue of the jobs run by the JobRunner thread. A job stays in the queue
+/// until it is completed, so a job interrupted by a crash is run again on the next start.
+#[derive(Clone)]
+pub struct JobQueue {
+    jobs: Arc<(Mutex<VecDeque<Job>>, Condvar)>,
+}
+
+impl JobQueue {
+    pub fn load() -> Self {
+        let jobs: VecDeque<Job> = match fs::read_to_string(JOBS_FILE) {
+            Ok(jobs) => serde_json::from_str(&jobs).unwrap_or_else(|e| {
+                error!("Invalid jobs file {}, dropping its jobs: {}", JOBS_FILE, e);
+                VecDeque::new()
+            }),
+            Err(_) => VecDeque::new(),
+        };
+        if !jobs.is_empty() {
+            info!("Resuming {} jobs from {}", jobs.len(), JOBS_FILE);
+        }
+        Self {
+            jobs: Arc::new((Mutex::new(jobs), Condvar::new())),
+        }
+    }
+
+    fn persist(jobs: &VecDeque<Job>) {
+        let tmp_path = format!("{}.tmp", JOBS_FILE);
+        let json = serde_json::to_string_pretty(jobs).expect("Error serializing jobs");
+        fs::write(&tmp_path, json).expect(&format!("Error writing {}", tmp_path));
+        fs::rename(&tmp_path, JOBS_FILE)
+            .expect(&format!("Error moving {} to {}", tmp_path, JOBS_FILE));
+    }
+
+    fn read_failed() -> Vec<FailedJob> {
+        match fs::read_to_string(FAILED_JOBS_FILE) {
+            Ok(failed) => serde_json::from_str(&failed).unwrap_or_else(|e| {
+                error!("Invalid failed jobs file {}: {}", FAILED_JOBS_FILE, e);
+                vec![]
+            }),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Whether an encode of the segment is queued or failed, whatever its profile
+    pub fn encodes_segment(&self, segment: i64) -> bool {
+        let is_segment =
+            |job: &Job| matches!(job, Job::Encode { segment: queued, .. } if *queued == segment);
+        let (jobs, _) = &*self.jobs;
+        jobs.lock()
+            .expect("Jobs lock poisoned")
+            .iter()
+            .any(is_segment)
+            || Self::read_failed()
+                .iter()
+                .any(|failed| is_segment(&failed.job))
+    }
+
+    pub fn push(&self, job: Job) {
+        let (jobs, changed) = &*self.jobs;
+        let mut jobs = jobs.lock().expect("Jobs lock poisoned");
+        info!("Queueing {:?}, {} jobs pending", job, jobs.len());
+        jobs.push_back(job);
+        Self::persist(&jobs);
+        changed.notify_all();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let (jobs, _) = &*self.jobs;
+        jobs.lock().expect("Jobs lock poisoned").is_empty()
+    }
+
+    /// Waits for a job, without removing it from the queue
+    pub fn next(&self) -> Job {
+        let (jobs, changed) = &*self.jobs;
+        let mut jobs = jobs.lock().expect("Jobs lock poisoned");
+        loop {
+            if let Some(job) = jobs.front() {
+                return job.clone();
+            }
+            jobs = changed.wait(jobs).expect("Jobs lock poisoned");
+        }
+    }
+
+    /// Removes the job returned by next
+    pub fn complete(&self) {
+        let (jobs, changed) = &*self.jobs;
+        let mut jobs = jobs.lock().expect("Jobs lock poisoned");
+        jobs.pop_front();
+        Self::persist(&jobs);
+        changed.notify_all();
+    }
+
+    /// Removes the job returned by next, keeping it in FAILED_JOBS_FILE
+    pub fn fail(&self, reason: String) {
+        let (jobs, changed) = &*self.jobs;
+        let mut jobs = jobs.lock().expect("Jobs lock poisoned");
+        if let Some(job) = jobs.pop_front() {
+            let mut failed = Self::read_failed();
+            failed.push(FailedJob {
+                job,
+                reason,
+                failed_at: Local::now().timestamp(),
+            });
+            let json = serde_json::to_string_pretty(&failed).expect("Error serializing jobs");
+            if let Err(e) = fs::write(FAILED_JOBS_FILE, json) {
+                error!("Error writing {}: {}", FAILED_JOBS_FILE, e);
+            }
+        }
+        Self::persist(&jobs);
+        changed.notify_all();
+    }
+
+    /// Encode jobs waiting behind the one running
+    pub fn encode_backlog(&self) -> usize {
+        let (jobs, _) = &*self.jobs;
+        let jobs = jobs.lock().expect("Jobs lock poisoned");
+        jobs.iter()
+            .skip(1)
+            .filter(|job| matches!(job, Job::Encode { .. }))
+            .count()
+    }
+
+    pub fn wait_until_empty(&self) {
+        let (jobs, changed) = &*self.jobs;
+        let mut jobs = jobs.lock().expect("Jobs lock poisoned");
+        while !jobs.is_empty() {
+            jobs = changed.wait(jobs).expect("Jobs lock poisoned");
+        }
+    }
+}
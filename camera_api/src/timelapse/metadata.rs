@@ -0,0 +1,110 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Catalog metadata of a movie, stored next to it: 1234.mp4 -> 1234.meta.json
+/// Title, notes, tags and pins are edited through video_streaming_api.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct MovieMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// pinned movies are protected from deletion
+    #[serde(default)]
+    pub pinned: bool,
+    /// set once the archive transcode replaced the movie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcoded: Option<Transcoded>,
+    /// archive transcodes started, the movie is given up on after the max_attempts of the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcode_attempts: Option<u32>,
+    /// set by the recorder when the frames nearly identical to the previous one are dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<FrameCounts>,
+    /// fields this version doesn't know about, kept untouched
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// How a day movie of the archive was re-encoded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Transcoded {
+    /// codecs as named by ffprobe, e.g. h264 and hevc
+    pub from_codec: Option<String>,
+    pub codec: Option<String>,
+    /// encoder profile of the recorder config
+    pub profile: String,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub transcoded_at: i64,
+}
+
+/// Frames captured for a movie, kept in it or dropped as nearly identical to the last kept one
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct FrameCounts {
+    pub kept: u32,
+    pub dropped: u32,
+}
+
+pub fn metadata_filename(movie_stem: &str) -> String {
+    format!("{}.meta.json", movie_stem)
+}
+
+impl MovieMetadata {
+    /// Movies without metadata file have the default, empty, metadata
+    pub fn read(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(metadata) => serde_json::from_str(&metadata).unwrap_or_else(|e| {
+                error!("Invalid metadata {}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Writes to a temporary file first so the API never reads a partial file
+    pub fn write(&self, path: &str) {
+        let tmp_path = format!("{}.tmp", path);
+        let metadata = serde_json::to_string_pretty(self).expect("Error serializing metadata");
+        fs::write(&tmp_path, metadata).expect(&format!("Error writing {}", tmp_path));
+        fs::rename(&tmp_path, path).expect(&format!("Error moving {} to {}", tmp_path, path));
+    }
+
+    /// Carries the metadata of an hourly movie over to the day movie it is stitched into,
+    /// so pinning or tagging an hour protects and tags the whole day. Frame counts add up.
+    pub fn merge_hour(&mut self, hour_label: &str, hour: MovieMetadata) {
+        self.pinned |= hour.pinned;
+        if let Some(hour_frames) = hour.frames {
+            let frames = self.frames.get_or_insert_with(FrameCounts::default);
+            frames.kept += hour_frames.kept;
+            frames.dropped += hour_frames.dropped;
+        }
+        for tag in hour.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+        let hour_notes: Vec<String> = hour.title.into_iter().chain(hour.notes).collect();
+        if !hour_notes.is_empty() {
+            let hour_notes = format!("{}: {}", hour_label, hour_notes.join(" - "));
+            self.notes = Some(match self.notes.take() {
+                Some(notes) => format!("{}\n{}", notes, hour_notes),
+                None => hour_notes,
+            });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.notes.is_none()
+            && self.tags.is_empty()
+            && !self.pinned
+            && self.transcoded.is_none()
+            && self.transcode_attempts.is_none()
+            && self.frames.is_none()
+            && self.other.is_empty()
+    }
+}
@@ -1,48 +1,141 @@
 use crate::camera_api::Camera;
+use crate::config::{CaptureIntervalConfig, EncoderProfile, FrameDedupConfig, RecorderConfig};
+use crate::disk::disk_usage_percent;
+use crate::events::{EncodeProgress, EventPublisher, RecorderEvent};
+use crate::metrics;
+use crate::systemd;
+use crate::timelapse::archive::ArchiveTranscoder;
+use crate::timelapse::conform::{stitch_work_dir, ConformedClips};
+use crate::timelapse::dedup::{read_frame_counts, write_frame_counts, FrameFilter, Luma};
+use crate::timelapse::encoder::{FRAMERATE, RESOLUTION};
+use crate::timelapse::frames::{frame_index_filename, FrameIndex, FrameLog};
+use crate::timelapse::interval::CaptureScheduler;
+use crate::timelapse::jobs::{Job, JobQueue};
+use crate::timelapse::metadata::{metadata_filename, MovieMetadata};
+use crate::timelapse::probe::{probe, probed_codec, quarantine, verify, Expected, Probe};
+use crate::timelapse::thumbnails::{sprite_filename, stitch_vtt, vtt_filename};
 use chrono::prelude::*;
-use chrono::Duration;
 use crossbeam_channel::Receiver;
 use log::{error, info};
+use serde::Serialize;
+use std::any::Any;
 use std::fs;
-use std::io::Write;
-use std::ops::Sub;
+use std::panic::{self, AssertUnwindSafe};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+mod archive;
+mod conform;
+mod dedup;
 mod encoder;
+mod frames;
+mod interval;
+mod jobs;
+mod metadata;
+#[cfg(feature = "native-encoder")]
+mod native;
+mod probe;
+mod subtitles;
+mod thumbnails;
 
 const PICS_FOLDER_ROOT: &str = "/mnt/skynet/pics";
 const MOVIES_FOLDER_ROOT: &str = "/mnt/skynet/movies";
 const ENCODING_FOLDER: &str = "/mnt/skynet/encoding";
+/// Pictures of the segments that could not be encoded, kept for a manual re-encode along with a
+/// failure.json explaining why. Outside PICS_FOLDER_ROOT so they are never picked up again.
+const FAILED_SEGMENTS_FOLDER: &str = "/mnt/skynet/failed_segments";
+/// Written in a today folder whose stitch failed, with the reason. The folder is kept, its clips
+/// are still served, but it is no longer the today folder.
+const UNSTITCHED_FILENAME: &str = "unstitched.json";
+/// Encode progress events are published at most this often
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
 pub enum PicTakingMessage {
-    Done,
+    /// number of pictures kept
+    Done(u32),
 }
 
 pub struct EncodingOutput {
     output_path_with_filename: String,
     filename: String,
 }
+/// Why ffmpeg failed, the movie is not kept
+pub struct EncodingFailure {
+    exit_code: Option<i32>,
+    /// last lines ffmpeg printed
+    stderr_tail: String,
+}
+/// Values of the ffmpeg -progress output
+#[derive(Clone, Debug, Default)]
+pub struct FfmpegProgress {
+    pub frame: u64,
+    pub fps: f64,
+    pub out_time_millis: i64,
+    pub speed: Option<f64>,
+}
 pub enum EncodingMessage {
+    /// sent about twice a second while ffmpeg runs
+    Progress(FfmpegProgress),
     Done(EncodingOutput),
+    Failed(EncodingFailure),
+}
+
+/// Written as UNSTITCHED_FILENAME in the hourly clips folder of a day that could not be stitched
+#[derive(Debug, Serialize)]
+struct StitchFailure {
+    day: i64,
+    reason: String,
+    failed_at: i64,
+}
+
+/// Written as failure.json next to the pictures of a segment that could not be encoded
+#[derive(Debug, Serialize)]
+struct SegmentFailure {
+    segment: i64,
+    exit_code: Option<i32>,
+    stderr_tail: String,
+    /// encoder profile of the config
+    profile: String,
+    /// presets tried, in order, none for the encoders without presets
+    presets: Vec<String>,
+    failed_at: i64,
 }
 pub struct TimeLapseManufacturer {
     camera: Camera,
-    curr_tmp_pic_recording_folder: PicsFolders,
     picture_taking_thread: Option<(chrono::DateTime<Local>, Receiver<PicTakingMessage>)>,
+    events: EventPublisher,
+    jobs: JobQueue,
+    frame_dedup: FrameDedupConfig,
+    capture_interval: CaptureIntervalConfig,
+    /// None if the archive transcode is disabled
+    archive_transcoder: Option<JoinHandle<()>>,
+    /// set by SIGTERM and SIGINT
+    shutdown: Arc<AtomicBool>,
+}
+
+/// Runs the encode and stitch jobs in its own thread, so they never delay taking pictures
+pub struct JobRunner {
     encoding_thread: Option<Receiver<EncodingMessage>>,
+    events: EventPublisher,
+    config: RecorderConfig,
+    jobs: JobQueue,
 }
 
+/// Folder where the pictures of a segment are taken, named after the timestamp the segment
+/// started at. It is kept until the segment is encoded, so any number of them may be pending.
 #[derive(Clone, Debug, PartialEq)]
-enum PicsFolders {
-    A,
-    B,
+struct SegmentFolder {
+    started_at: i64,
 }
 
-impl PicsFolders {
+impl SegmentFolder {
     pub fn path(&self) -> String {
-        format!("{}/{}", PICS_FOLDER_ROOT, self.to_string())
+        format!("{}/{}", PICS_FOLDER_ROOT, self.started_at)
     }
+
     pub fn delete_folder(&self) {
         info!("Deleting folder: {}", self.path());
         if std::fs::read_dir(self.path()).is_ok() {
@@ -51,18 +144,6 @@ impl PicsFolders {
         }
     }
 
-    pub fn reset_folder(&self) {
-        self.delete_folder();
-        self.create_folder();
-    }
-
-    pub fn get_other_one(&self) -> PicsFolders {
-        return match &self {
-            PicsFolders::A => PicsFolders::B,
-            PicsFolders::B => PicsFolders::A,
-        };
-    }
-
     pub fn create_folder(&self) {
         info!("Creating dir: {}", self.path());
         fs::create_dir_all(self.path()).expect(&format!(
@@ -71,23 +152,42 @@ impl PicsFolders {
         ));
     }
 
-    pub fn switch_folders(&mut self) {
-        match self {
-            PicsFolders::A => {
-                *self = PicsFolders::B;
-            }
-            PicsFolders::B => {
-                *self = PicsFolders::A;
-            }
-        };
+    pub fn picture_count(&self) -> usize {
+        fs::read_dir(self.path())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_name().to_string_lossy().ends_with(".jpg"))
+                    .count()
+            })
+            .unwrap_or(0)
     }
-}
-impl ToString for PicsFolders {
-    fn to_string(&self) -> String {
-        match &self {
-            PicsFolders::A => "a".to_string(),
-            PicsFolders::B => "b".to_string(),
+
+    /// Segment folders left in PICS_FOLDER_ROOT, oldest first. Anything else there is removed.
+    pub fn existing() -> Vec<SegmentFolder> {
+        fs::create_dir_all(PICS_FOLDER_ROOT).expect("Error creating pics folder");
+        let mut folders = vec![];
+        for entry in fs::read_dir(PICS_FOLDER_ROOT).expect("Error reading pics folder") {
+            let entry = entry.expect("Error reading entry from pics folder");
+            match entry.file_name().to_string_lossy().parse::<i64>() {
+                Ok(started_at) if entry.path().is_dir() => {
+                    folders.push(SegmentFolder { started_at })
+                }
+                _ => {
+                    info!("Removing unknown {:?} from pics folder", entry.path());
+                    let removed = if entry.path().is_dir() {
+                        fs::remove_dir_all(entry.path())
+                    } else {
+                        fs::remove_file(entry.path())
+                    };
+                    if let Err(e) = removed {
+                        error!("Error removing {:?}: {}", entry.path(), e);
+                    }
+                }
+            }
         }
+        folders.sort_by_key(|folder| folder.started_at);
+        folders
     }
 }
 
@@ -124,6 +224,7 @@ impl TimeLapseManufacturer {
     /// All the files in MOVIES_FOLDER_ROOT should either be a folder (maximum of one folder, the today folder)
     /// with its name being a timestamp or a file, with its name being a timestamp and extension .mp4
     /// The folder should also contain files with extension .mp4 and named a timestamp number.
+    /// Folders of the days that could not be stitched, holding UNSTITCHED_FILENAME, are left out.
     pub fn get_dir_structure() -> DirStructure {
         let mut dir_structure: DirStructure = DirStructure {
             movies: vec![],
@@ -151,7 +252,7 @@ impl TimeLapseManufacturer {
                     datetime,
                     path: entry.path().to_string_lossy().to_string(),
                 })
-            } else if metadata.is_dir() {
+            } else if metadata.is_dir() && !entry.path().join(UNSTITCHED_FILENAME).exists() {
                 let filename = entry.file_name();
                 let timestamp = filename.to_string_lossy();
                 if let Ok(timestamp) = timestamp.parse::<i64>() {
@@ -196,123 +297,98 @@ impl TimeLapseManufacturer {
         }
         dir_structure
     }
-    pub fn new() -> Self {
-        info!("Clearing pics folder");
-        Self::clear_pics_folder();
-        Self {
-            camera: Camera::new(),
-            curr_tmp_pic_recording_folder: PicsFolders::A,
-            picture_taking_thread: None,
-            encoding_thread: None,
-        }
-    }
-
-    fn clear_pics_folder() {
-        if fs::read_dir(PICS_FOLDER_ROOT).is_ok() {
-            info!("Found previous pic dir. Removing dir: {}", PICS_FOLDER_ROOT);
-            fs::remove_dir_all(PICS_FOLDER_ROOT).expect("Error removing PICS folder");
+    pub fn new(config: RecorderConfig, shutdown: Arc<AtomicBool>) -> Self {
+        let events = EventPublisher::new(&config);
+        if let Some(address) = &config.metrics_address {
+            metrics::serve(address, MOVIES_FOLDER_ROOT);
         }
-        PicsFolders::A.create_folder();
-        PicsFolders::B.create_folder();
-    }
-
-    pub fn encode_last_hour_and_move_to_today_folder_clean_pic_folder(&mut self) {
-        // start encoding the pics just taken
-        let last_hour_timestamp = Local::now().sub(Duration::minutes(30)).timestamp();
-        // get the folder not currently active, the one which just finished being filled with photos
-        let pics_folder = self.curr_tmp_pic_recording_folder.get_other_one();
-        let encoded_movie_filename = format!("{}.mp4", last_hour_timestamp);
-        let tmp_output_dir = format!("{}/{}", ENCODING_FOLDER, "today");
-        if fs::read_dir(&tmp_output_dir).is_err() {
-            info!("Creating new today folder at: {}", &tmp_output_dir);
-            fs::create_dir_all(&tmp_output_dir).expect(&format!(
-                "Error creating dir for encoding: {}",
-                tmp_output_dir
-            ));
+        if fs::read_dir(ENCODING_FOLDER).is_ok() {
+            info!("Encoding folder found! Removing previous encoding folder");
+            fs::remove_dir_all(ENCODING_FOLDER).expect("Error removing previous enconding folder!");
         }
-
-        self.start_encoding_thread(
-            format!("{}", pics_folder.path()),
-            format!("{}/{}", tmp_output_dir, encoded_movie_filename),
-            encoded_movie_filename.to_string(),
-        );
-
-        // wait encoding to be over and get output path
-        info!("Waiting for encoding thread...");
-        let encoding_output = match self
-            .encoding_thread
-            .as_mut()
-            .expect("Encoding thread panicked!")
-            .recv()
-            .expect("Encoding thread did not send done MSG!")
-        {
-            EncodingMessage::Done(output_path) => output_path,
-        };
-        info!("Enconding of last hour done! Deleting pics from folder.");
-        pics_folder.reset_folder();
-        // check if we already a "today" folder, if not create one
-        if Self::get_dir_structure().today_folder.is_none() {
-            let today_folder_path = format!(
-                "{}/{}",
-                MOVIES_FOLDER_ROOT,
-                chrono::Local::now().timestamp()
-            );
-            info!("No today folder yet, creating one at {}", today_folder_path);
-            fs::create_dir_all(&today_folder_path)
-                .expect(&format!("Error creating {}", today_folder_path));
+        let jobs = JobQueue::load();
+        // pictures of segments interrupted by a crash are encoded too
+        for folder in SegmentFolder::existing() {
+            if !jobs.encodes_segment(folder.started_at) {
+                info!("Found pictures of unfinished segment {}", folder.path());
+                jobs.push(Job::Encode {
+                    segment: folder.started_at,
+                    profile: None,
+                });
+            }
         }
-
-        let today_folder = Self::get_dir_structure().today_folder;
-        let today_folder = today_folder.expect("Error creating today folder");
-        let dest_path_with_filename = format!("{}/{}", today_folder.path, encoding_output.filename);
-
-        // move the encoded movie into today folder
-        info!(
-            "Moving the encoded file from {} to {}",
-            encoding_output.output_path_with_filename, dest_path_with_filename
-        );
-        fs::rename(
-            &encoding_output.output_path_with_filename,
-            &dest_path_with_filename,
+        let archive_transcoder = ArchiveTranscoder::new(
+            config.clone(),
+            jobs.clone(),
+            events.clone(),
+            shutdown.clone(),
         )
-        .expect(&format!(
-            "Error moving {} to {}",
-            encoding_output.output_path_with_filename, dest_path_with_filename
-        ));
+        .start();
+        let frame_dedup = config.frame_dedup.clone();
+        let capture_interval = config.capture_interval.clone();
+        JobRunner {
+            encoding_thread: None,
+            events: events.clone(),
+            config,
+            jobs: jobs.clone(),
+        }
+        .start();
+        Self {
+            camera: Camera::new(events.clone()),
+            picture_taking_thread: None,
+            events,
+            jobs,
+            frame_dedup,
+            capture_interval,
+            archive_transcoder,
+            shutdown,
+        }
     }
 
     pub fn start_taking_pictures(&mut self) {
-        self.curr_tmp_pic_recording_folder.reset_folder();
         self.start_take_pictures_till_hour_end_thread();
     }
 
-    pub fn wait_taking_pictures(&mut self) {
+    /// Returns when the segment started and how many pictures were taken
+    pub fn wait_taking_pictures(&mut self) -> (DateTime<Local>, u32) {
         // wait for pic taking thread to be done
-        let _message = self
+        let (started_at, receiver) = self
             .picture_taking_thread
-            .as_mut()
-            .expect("There was no pic taking thread receiver after taking pics")
-            .1
+            .take()
+            .expect("There was no pic taking thread receiver after taking pics");
+        let PicTakingMessage::Done(frames) = receiver
             .recv()
             .expect("Pic taking thread did not send any msg");
-        self.picture_taking_thread = None;
+        (started_at, frames)
+    }
+
+    /// Stops the camera and waits for the queued jobs, including the encoding of the partial
+    /// segment whose pictures were just taken, and for the archive transcode to stop
+    fn shut_down(&mut self, frames: u32) {
+        info!(
+            "Shutting down, encoding the {} pictures of the current segment",
+            frames
+        );
+        systemd::notify("STOPPING=1\nSTATUS=Encoding the current segment before exiting");
+        self.camera.stop();
+        self.jobs.wait_until_empty();
+        if let Some(archive_transcoder) = self.archive_transcoder.take() {
+            if archive_transcoder.join().is_err() {
+                error!("Archive transcode thread panicked");
+            }
+        }
+        info!("Shutdown done");
     }
 
-    /// Start pic taking at current folder
+    /// Start pic taking in a new segment folder
     /// Wait Pic taking done
-    /// When done switch folder and restart
-    /// start encoding to TMP folder, wait for it, move from TMP to today folder
-    /// check need stitching, if so do it and wait for it
-    /// wait pic taking done, switch folder
+    /// When done start pic taking again, in a new folder
+    /// queue the encoding of the segment, which the JobRunner moves to the today folder
+    /// check need stitching, if so queue it after the encoding
+    /// On shutdown, wait for the queued jobs instead of starting a new pic taking
     pub fn run(&mut self) {
         // Starting Pic taking
         println!("{:#?}", Self::get_dir_structure());
-        if fs::read_dir(ENCODING_FOLDER).is_ok() {
-            info!("Encoding folder found! Removing previous encoding folder");
-            fs::remove_dir_all(ENCODING_FOLDER).expect("Error removing previous enconding folder!");
-        }
-        PicsFolders::A.delete_folder();
-        PicsFolders::B.delete_folder();
         loop {
             let start_pic_day = match &self.picture_taking_thread {
                 None => {
@@ -322,18 +398,21 @@ impl TimeLapseManufacturer {
                 Some(t) => t.0.day(),
             };
             info!("Waiting pic taking to finish.");
-            self.wait_taking_pictures();
+            let (segment_started_at, frames) = self.wait_taking_pictures();
             info!("Pic taking done!");
-            info!("Switching pic taking folder!");
-            self.curr_tmp_pic_recording_folder.switch_folders();
-            info!(
-                "New pic taking folder: {}",
-                self.curr_tmp_pic_recording_folder.path()
-            );
+            let encode = Job::Encode {
+                segment: segment_started_at.timestamp(),
+                profile: None,
+            };
+            if self.shutdown.load(Ordering::Relaxed) {
+                self.jobs.push(encode);
+                self.shut_down(frames);
+                return;
+            }
             info!("Starting new pic taking thread!");
             self.start_taking_pictures();
-            info!("Encoding last hour!");
-            self.encode_last_hour_and_move_to_today_folder_clean_pic_folder();
+            info!("Queueing encoding of last hour!");
+            self.jobs.push(encode);
             let curr_pic_taking_day = self
                 .picture_taking_thread
                 .as_ref()
@@ -342,92 +421,611 @@ impl TimeLapseManufacturer {
                 .day();
 
             if start_pic_day != curr_pic_taking_day {
-                info!("New day is {}, stitching last day", start_pic_day);
+                info!(
+                    "New day is {}, queueing stitching of last day",
+                    start_pic_day
+                );
                 // new day, stitch last day and move it to own folder
-                self.stitch();
+                self.jobs.push(Job::Stitch);
             }
         }
     }
 
-    fn stitch(&mut self) {
-        info!("Started stitching!");
-        let mut files_string = String::new();
-        let structure = Self::get_dir_structure();
-        if let Some(folder) = structure.today_folder {
-            let mut movies = folder.today_movies;
-            movies.sort_by_key(|m| m.timestamp);
-            for movie in &movies {
-                files_string.push_str(&format!("file \'{}\'\n", movie.path));
-            }
-
-            let mut file = fs::File::create("files.txt").unwrap();
-            file.write_all(files_string.as_bytes()).unwrap();
-            // ffmpeg -f concat -safe 0 -i files.txt -c copy some.mp4
-            let out_path = format!("{}/{}.mp4", ENCODING_FOLDER, folder.timestamp);
-            info!("Outputting stitched result to {}!", out_path);
-            let process = Command::new("ffmpeg")
-                .stdout(Stdio::piped())
-                .arg("-f")
-                .arg("concat")
-                .arg("-safe")
-                .arg("0")
-                .arg("-i")
-                .arg("files.txt")
-                .arg("-c")
-                .arg("copy")
-                .arg(&out_path)
-                .spawn()
-                .expect("command failed to start");
-            let output = process
-                .wait_with_output()
-                .expect("Error waiting stitching process to end");
-            if !output.status.success() {
-                let err = String::from_utf8_lossy(&output.stderr);
-                let out = String::from_utf8_lossy(&output.stdout);
-                error!("Stitching process did not end successfully");
-                error!("{}", err);
-                error!("{}", out);
-            }
-            info!("Stitching done!");
-            info!("Removing previous today folder!");
-            fs::remove_dir_all(&folder.path).expect("Error removing today folder");
-            let dest = format!("{}.mp4", &folder.path);
-            info!("Moving result from {} to {}.", out_path, dest);
-            fs::rename(&out_path, dest)
-                .expect(&format!("Error moving {} to {}", out_path, folder.path));
-        }
-    }
-
     fn start_take_pictures_till_hour_end_thread(&mut self) -> JoinHandle<()> {
         let camera_process = self.camera.clone();
-        let recording_folder = self.curr_tmp_pic_recording_folder.clone();
+        let events = self.events.clone();
+        let shutdown = self.shutdown.clone();
+        let mut frame_filter = FrameFilter::new(self.frame_dedup.clone());
+        let mut scheduler = CaptureScheduler::new(self.capture_interval.clone(), &self.frame_dedup);
+        let started_at = chrono::Local::now();
+        let recording_folder = SegmentFolder {
+            started_at: started_at.timestamp(),
+        };
+        recording_folder.create_folder();
         let (sender, receiver) = crossbeam_channel::bounded::<PicTakingMessage>(2);
-        info!("Starting new pic taking!");
+        info!("Starting new pic taking in {}!", recording_folder.path());
         if self.picture_taking_thread.is_some() {
             panic!("Tried to start a new picture_taking_thread with one already existing!");
         }
-        self.picture_taking_thread = Some((chrono::Local::now(), receiver));
+        self.picture_taking_thread = Some((started_at, receiver));
         std::thread::spawn(move || {
-            let local: DateTime<Local> = Local::now();
+            let local: DateTime<Local> = started_at;
             let initial_hour = local.hour();
             info!(
                 "Pic taking thread started, taking pics for hour: {}",
                 initial_hour
             );
+            let mut frame_log = FrameLog::create(&recording_folder.path());
+            events.publish(RecorderEvent::SegmentStarted {
+                started_at: local.timestamp_millis(),
+            });
+            // number of the next kept frame, they are numbered without gaps for ffmpeg
             let mut i = 0;
-            // take pictures until the current hour expires or at least 5 pictures
-            while (Local::now().hour() == initial_hour) || i < 5 {
-                let path = format!(
-                    "{}/{}/{:05}.jpg",
-                    PICS_FOLDER_ROOT,
-                    recording_folder.to_string(),
-                    i
+            let mut captured = 0;
+            // take pictures until the current hour expires or at least 5 pictures, unless shutting
+            // down
+            while !shutdown.load(Ordering::Relaxed)
+                && ((Local::now().hour() == initial_hour) || captured < 5)
+            {
+                let captured_at = Local::now();
+                let triggered_at = Instant::now();
+                let pic = camera_process.take_new_pic();
+                captured += 1;
+                metrics::inc(&metrics::FRAMES_CAPTURED);
+                // decoded once for both
+                let luma = if frame_filter.compares() || scheduler.adapts() {
+                    Luma::of(&pic)
+                } else {
+                    None
+                };
+                let kept = frame_filter.keep(luma.as_ref());
+                let interval = scheduler.next_interval(luma);
+                metrics::set(
+                    &metrics::CAPTURE_INTERVAL_MILLIS,
+                    interval.as_millis() as u64,
                 );
-                camera_process.take_new_pic_save_at(&path);
-                i += 1;
+                if kept {
+                    let path = format!("{}/{:05}.jpg", recording_folder.path(), i);
+                    Camera::save_pic(&pic, &path);
+                    frame_log.record(i, captured_at);
+                } else {
+                    metrics::inc(&metrics::FRAMES_DROPPED);
+                }
+                events.publish(RecorderEvent::FrameCaptured {
+                    frame: i,
+                    captured_at: captured_at.timestamp_millis(),
+                    kept,
+                });
+                // systemd restarts the recorder if the pings stop, WatchdogSec= in the unit
+                systemd::notify(&format!(
+                    "WATCHDOG=1\nSTATUS=Recording segment started at {}, last frame at {}",
+                    local.format("%H:%M"),
+                    captured_at.format("%X")
+                ));
+                if kept {
+                    i += 1;
+                }
+                // the interval includes the capture, which takes about a second
+                while !shutdown.load(Ordering::Relaxed) && triggered_at.elapsed() < interval {
+                    std::thread::sleep(
+                        interval
+                            .saturating_sub(triggered_at.elapsed())
+                            .min(Duration::from_millis(500)),
+                    );
+                }
             }
+            write_frame_counts(&recording_folder.path(), &frame_filter.counts);
+            events.publish(RecorderEvent::SegmentEnded {
+                frames: i,
+                dropped: frame_filter.counts.dropped,
+            });
             info!("Pic taking thread done!");
-            sender.send(PicTakingMessage::Done).unwrap();
+            sender.send(PicTakingMessage::Done(i)).unwrap();
         })
     }
 }
+
+impl JobRunner {
+    pub fn start(mut self) {
+        std::thread::spawn(move || loop {
+            let job = self.jobs.next();
+            info!("Running {:?}", job);
+            // a job that fails is set aside, so it neither stops the jobs queued after it nor
+            // fails again on every start
+            let result = panic::catch_unwind(AssertUnwindSafe(|| self.run_job(job.clone())))
+                .unwrap_or_else(|panic| Err(panic_message(panic)));
+            match result {
+                Ok(()) => self.jobs.complete(),
+                Err(reason) => {
+                    error!("{:?} failed: {}", job, reason);
+                    self.encoding_thread = None;
+                    self.events.publish(RecorderEvent::JobFailed {
+                        job: serde_json::to_string(&job).expect("Error serializing job"),
+                        reason: reason.clone(),
+                    });
+                    self.jobs.fail(reason);
+                }
+            }
+        });
+    }
+
+    fn run_job(&mut self, job: Job) -> Result<(), String> {
+        match job {
+            Job::Encode { segment, profile } => self.encode_segment(
+                SegmentFolder {
+                    started_at: segment,
+                },
+                profile,
+            ),
+            Job::Stitch => self.stitch(),
+        }
+    }
+
+    /// Publishes the progress of the encoding thread until it is done
+    fn wait_encoding(
+        &mut self,
+        movie: &str,
+        total_frames: u64,
+    ) -> Result<EncodingOutput, EncodingFailure> {
+        let receiver = self
+            .encoding_thread
+            .take()
+            .expect("Encoding thread panicked!");
+        let mut published_at: Option<Instant> = None;
+        loop {
+            let message = receiver.recv().map_err(|_| EncodingFailure {
+                exit_code: None,
+                stderr_tail: "Encoding thread ended without a result".to_string(),
+            })?;
+            match message {
+                EncodingMessage::Progress(progress) => {
+                    if published_at.map_or(false, |at| at.elapsed() < PROGRESS_INTERVAL) {
+                        continue;
+                    }
+                    published_at = Some(Instant::now());
+                    self.events
+                        .publish(RecorderEvent::EncodeProgress(EncodeProgress::new(
+                            movie,
+                            total_frames,
+                            progress,
+                        )));
+                }
+                EncodingMessage::Done(output) => return Ok(output),
+                EncodingMessage::Failed(failure) => return Err(failure),
+            }
+        }
+    }
+
+    /// Moves the pictures of a segment that could not be encoded to FAILED_SEGMENTS_FOLDER, so
+    /// they are not lost and can be encoded by hand. If they cannot be moved they are left in
+    /// place, along with the failure.
+    fn keep_failed_segment(
+        &self,
+        pics_folder: &SegmentFolder,
+        movie: String,
+        failure: EncodingFailure,
+        profile: String,
+        presets: Vec<String>,
+    ) {
+        let mut failed_folder = format!("{}/{}", FAILED_SEGMENTS_FOLDER, pics_folder.started_at);
+        error!(
+            "Giving up encoding {}, keeping its pictures at {}",
+            pics_folder.path(),
+            failed_folder
+        );
+        let moved = fs::create_dir_all(FAILED_SEGMENTS_FOLDER)
+            .and_then(|_| fs::rename(pics_folder.path(), &failed_folder));
+        if let Err(e) = moved {
+            error!(
+                "Error moving {} to {}, leaving it in place: {}",
+                pics_folder.path(),
+                failed_folder,
+                e
+            );
+            failed_folder = pics_folder.path();
+        }
+        let segment_failure = SegmentFailure {
+            segment: pics_folder.started_at,
+            exit_code: failure.exit_code,
+            stderr_tail: failure.stderr_tail.clone(),
+            profile,
+            presets,
+            failed_at: Local::now().timestamp(),
+        };
+        let failure_path = format!("{}/failure.json", failed_folder);
+        let json =
+            serde_json::to_string_pretty(&segment_failure).expect("Error serializing failure");
+        if let Err(e) = fs::write(&failure_path, json) {
+            error!("Error writing {}: {}", failure_path, e);
+        }
+        self.events.publish(RecorderEvent::EncodeFailed {
+            movie,
+            exit_code: failure.exit_code,
+            stderr_tail: failure.stderr_tail,
+        });
+    }
+
+    /// Encoding is slowed down by a slower preset, which is only used while the encoder keeps up.
+    /// None for the encoders without presets.
+    fn preset(&self, profile: &EncoderProfile) -> Option<String> {
+        let presets = &profile.presets;
+        let backlog = self.jobs.encode_backlog();
+        presets.get(backlog).or_else(|| presets.last()).cloned()
+    }
+
+    /// Profile named by a job, the configured one if it names none or an unknown one
+    fn profile(&self, name: Option<String>) -> (String, EncoderProfile) {
+        let name = name.unwrap_or_else(|| self.config.encoder_profile.clone());
+        match self.config.profile(&name) {
+            Some(profile) => (name, profile.clone()),
+            None => {
+                error!("Unknown encoder profile {}, using the configured one", name);
+                let name = self.config.encoder_profile.clone();
+                let profile = self
+                    .config
+                    .profile(&name)
+                    .expect("Configured encoder profile is checked on load")
+                    .clone();
+                (name, profile)
+            }
+        }
+    }
+
+    /// The movie is named after the segment start, when its first picture was taken
+    fn encode_segment(
+        &mut self,
+        pics_folder: SegmentFolder,
+        profile: Option<String>,
+    ) -> Result<(), String> {
+        if pics_folder.picture_count() == 0 {
+            info!("No pictures in {}, nothing to encode", pics_folder.path());
+            pics_folder.delete_folder();
+            return Ok(());
+        }
+        let encoded_movie_filename = format!("{}.mp4", pics_folder.started_at);
+        let tmp_output_dir = format!("{}/{}", ENCODING_FOLDER, "today");
+        if fs::read_dir(&tmp_output_dir).is_err() {
+            info!("Creating new today folder at: {}", &tmp_output_dir);
+            fs::create_dir_all(&tmp_output_dir).map_err(|e| {
+                format!("Error creating dir for encoding {}: {}", tmp_output_dir, e)
+            })?;
+        }
+
+        // a failure may come from the preset on a loaded Pi, so it is retried once with the
+        // fallback preset before giving up on the segment
+        let total_frames = pics_folder.picture_count() as u64;
+        let (profile_name, profile) = self.profile(profile);
+        let mut presets = vec![self.preset(&profile)];
+        if profile.fallback_preset.is_some() && presets[0] != profile.fallback_preset {
+            presets.push(profile.fallback_preset.clone());
+        }
+        let mut attempted = vec![];
+        let mut failure = None;
+        let mut encoding_output = None;
+        for preset in presets {
+            info!(
+                "Encoding {} with profile {} and preset {:?}",
+                pics_folder.path(),
+                profile_name,
+                preset
+            );
+            attempted.extend(preset.clone());
+            self.start_encoding_thread(
+                format!("{}", pics_folder.path()),
+                format!("{}/{}", tmp_output_dir, encoded_movie_filename),
+                encoded_movie_filename.to_string(),
+                profile.ffmpeg_args(preset.as_deref()),
+                Expected {
+                    duration_secs: Some(total_frames as f64 / FRAMERATE as f64),
+                    codec: probed_codec(&profile.codec),
+                    resolution: Some(RESOLUTION),
+                },
+            );
+
+            // wait encoding to be over and get output path
+            info!("Waiting for encoding thread...");
+            match self.wait_encoding(&encoded_movie_filename, total_frames) {
+                Ok(output_path) => {
+                    encoding_output = Some(output_path);
+                    break;
+                }
+                Err(encoding_failure) => {
+                    error!(
+                        "Encoding {} with profile {} failed",
+                        pics_folder.path(),
+                        profile_name
+                    );
+                    failure = Some(encoding_failure);
+                }
+            }
+        }
+        let encoding_output = match (encoding_output, failure) {
+            (Some(encoding_output), _) => encoding_output,
+            (None, Some(failure)) => {
+                self.keep_failed_segment(
+                    &pics_folder,
+                    encoded_movie_filename,
+                    failure,
+                    profile_name,
+                    attempted,
+                );
+                return Ok(());
+            }
+            (None, None) => unreachable!("No preset was attempted"),
+        };
+        let frame_counts = read_frame_counts(&pics_folder.path());
+        info!("Enconding of last hour done! Deleting pics folder.");
+        pics_folder.delete_folder();
+        // check if we already a "today" folder, if not create one, named after the segment as
+        // the encoding may run late
+        if TimeLapseManufacturer::get_dir_structure()
+            .today_folder
+            .is_none()
+        {
+            let today_folder_path = format!("{}/{}", MOVIES_FOLDER_ROOT, pics_folder.started_at);
+            info!("No today folder yet, creating one at {}", today_folder_path);
+            fs::create_dir_all(&today_folder_path)
+                .map_err(|e| format!("Error creating {}: {}", today_folder_path, e))?;
+        }
+
+        let today_folder = TimeLapseManufacturer::get_dir_structure().today_folder;
+        let today_folder = today_folder.ok_or("Error creating today folder")?;
+        let dest_path_with_filename = format!("{}/{}", today_folder.path, encoding_output.filename);
+
+        // move the encoded movie into today folder
+        info!(
+            "Moving the encoded file from {} to {}",
+            encoding_output.output_path_with_filename, dest_path_with_filename
+        );
+        fs::rename(
+            &encoding_output.output_path_with_filename,
+            &dest_path_with_filename,
+        )
+        .map_err(|e| {
+            format!(
+                "Error moving {} to {}: {}",
+                encoding_output.output_path_with_filename, dest_path_with_filename, e
+            )
+        })?;
+
+        // move the thumbnails and frame index generated along with the movie next to it
+        let movie_stem = encoding_output.filename.replace(".mp4", "");
+        for sidecar in &[
+            sprite_filename(&movie_stem),
+            vtt_filename(&movie_stem),
+            frame_index_filename(&movie_stem),
+        ] {
+            let sidecar_path = format!("{}/{}", tmp_output_dir, sidecar);
+            if fs::metadata(&sidecar_path).is_ok() {
+                let dest = format!("{}/{}", today_folder.path, sidecar);
+                fs::rename(&sidecar_path, &dest)
+                    .map_err(|e| format!("Error moving {} to {}: {}", sidecar_path, dest, e))?;
+            }
+        }
+        if let Some(frame_counts) = frame_counts {
+            let metadata_path = format!("{}/{}", today_folder.path, metadata_filename(&movie_stem));
+            let mut metadata = MovieMetadata::read(&metadata_path);
+            metadata.frames = Some(frame_counts);
+            metadata.write(&metadata_path);
+        }
+        self.events.publish(RecorderEvent::EncodeFinished {
+            movie: format!("{}/{}", today_folder.timestamp, encoding_output.filename),
+        });
+        self.check_disk_usage();
+        Ok(())
+    }
+
+    /// Called after each hour
+    fn check_disk_usage(&self) {
+        if let Some(used_percent) = disk_usage_percent(MOVIES_FOLDER_ROOT) {
+            if used_percent >= self.config.disk_low_percent {
+                error!("Movies disk is {}% full", used_percent);
+                self.events.publish(RecorderEvent::DiskLow {
+                    path: MOVIES_FOLDER_ROOT.to_string(),
+                    used_percent,
+                });
+            }
+        }
+    }
+
+    /// Concatenates the clips without re-encoding them, then checks the day movie
+    fn concat(clips: &ConformedClips, out_path: &str) -> Result<Probe, String> {
+        // ffmpeg -f concat -safe 0 -i files.txt -c copy some.mp4
+        info!("Outputting stitched result to {}!", out_path);
+        let process = Command::new("ffmpeg")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .arg("-f")
+            .arg("concat")
+            .arg("-safe")
+            .arg("0")
+            .arg("-i")
+            .arg(&clips.list_path)
+            .arg("-map")
+            .arg("0")
+            .arg("-c")
+            .arg("copy")
+            .arg(out_path)
+            .spawn()
+            .expect("command failed to start");
+        let output = process
+            .wait_with_output()
+            .expect("Error waiting stitching process to end");
+        if !output.status.success() {
+            let err = String::from_utf8_lossy(&output.stderr);
+            let out = String::from_utf8_lossy(&output.stdout);
+            error!("Stitching process did not end successfully");
+            error!("{}", err);
+            error!("{}", out);
+            return Err(format!("ffmpeg failed, {}", output.status));
+        }
+        verify(out_path, &clips.expected)
+    }
+
+    /// Without the native-encoder feature stitching always needs ffmpeg, see native.rs
+    #[cfg(not(feature = "native-encoder"))]
+    fn stitch_natively(
+        &self,
+        _movies: &[Movie],
+        _output_path: &str,
+        _framerate: u32,
+    ) -> Option<Result<(), String>> {
+        None
+    }
+
+    fn stitch(&mut self) -> Result<(), String> {
+        info!("Started stitching!");
+        let structure = TimeLapseManufacturer::get_dir_structure();
+        if let Some(folder) = structure.today_folder {
+            let folder_path = &folder.path;
+            let mut movies = folder.today_movies;
+            movies.sort_by_key(|m| m.timestamp);
+            if movies.is_empty() {
+                // every hour of the day was deleted through the API
+                info!("Today folder has no movies, removing it");
+                fs::remove_dir_all(&folder.path)
+                    .map_err(|e| format!("Error removing {}: {}", folder_path, e))?;
+                return Ok(());
+            }
+            self.events.publish(RecorderEvent::StitchStarted {
+                day: folder.timestamp,
+            });
+            let started_at = Instant::now();
+            let out_path = format!("{}/{}.mp4", ENCODING_FOLDER, folder.timestamp);
+            let day = folder.timestamp;
+            let native = self.stitch_natively(&movies, &out_path, FRAMERATE);
+            let verified = native.unwrap_or_else(|| {
+                self.conform_clips(day, &movies)
+                    .and_then(|clips| Self::concat(&clips, &out_path))
+                    .map(|_| ())
+            });
+            metrics::inc(&metrics::STITCHES);
+            metrics::add_duration(&metrics::STITCH_MILLIS, started_at.elapsed());
+            if let Ok(movie) = fs::metadata(&out_path) {
+                metrics::add(&metrics::BYTES_WRITTEN, movie.len());
+            }
+            let work_dir = stitch_work_dir(folder.timestamp);
+            if let Err(e) = fs::remove_dir_all(&work_dir) {
+                error!("Error removing {}: {}", work_dir, e);
+            }
+            if let Err(reason) = verified {
+                // the hourly clips are only removed once the day movie is valid, until then they
+                // stay served from their folder, marked so the next day starts a new today folder
+                metrics::inc(&metrics::STITCH_FAILURES);
+                if fs::metadata(&out_path).is_ok() {
+                    quarantine(&out_path, &reason);
+                }
+                let failure = StitchFailure {
+                    day: folder.timestamp,
+                    reason: reason.clone(),
+                    failed_at: Local::now().timestamp(),
+                };
+                let failure_path = format!("{}/{}", folder_path, UNSTITCHED_FILENAME);
+                let json =
+                    serde_json::to_string_pretty(&failure).expect("Error serializing failure");
+                if let Err(e) = fs::write(&failure_path, json) {
+                    error!("Error writing {}: {}", failure_path, e);
+                }
+                self.events.publish(RecorderEvent::StitchFailed {
+                    day: folder.timestamp,
+                    reason,
+                });
+                return Ok(());
+            }
+            info!("Stitching done!");
+            // the day thumbnails track keeps pointing to the hourly sprites, move them out
+            // of the today folder before removing it
+            let hourly_vtt_paths: Vec<String> = movies
+                .iter()
+                .map(|m| format!("{}/{}", folder_path, vtt_filename(&m.timestamp.to_string())))
+                .collect();
+            let day_vtt_path = format!(
+                "{}/{}",
+                MOVIES_FOLDER_ROOT,
+                vtt_filename(&folder.timestamp.to_string())
+            );
+            info!("Stitching thumbnails track to {}", day_vtt_path);
+            let hour_duration_millis = |hour: usize| {
+                probe(&movies[hour].path).map(|probe| (probe.duration_secs * 1000.) as u64)
+            };
+            stitch_vtt(&hourly_vtt_paths, hour_duration_millis, &day_vtt_path);
+            for movie in &movies {
+                let sprite = sprite_filename(&movie.timestamp.to_string());
+                let sprite_path = format!("{}/{}", folder.path, sprite);
+                if fs::metadata(&sprite_path).is_ok() {
+                    let dest = format!("{}/{}", MOVIES_FOLDER_ROOT, sprite);
+                    fs::rename(&sprite_path, &dest)
+                        .map_err(|e| format!("Error moving {} to {}: {}", sprite_path, dest, e))?;
+                }
+            }
+            // the day frame index is only valid if every hour has one
+            let hourly_indexes: Option<Vec<FrameIndex>> = movies
+                .iter()
+                .map(|m| {
+                    FrameIndex::read(&format!(
+                        "{}/{}",
+                        folder_path,
+                        frame_index_filename(&m.timestamp.to_string())
+                    ))
+                })
+                .collect();
+            match hourly_indexes {
+                Some(hourly_indexes) => {
+                    let day_index_path = format!(
+                        "{}/{}",
+                        MOVIES_FOLDER_ROOT,
+                        frame_index_filename(&folder.timestamp.to_string())
+                    );
+                    info!("Stitching frame index to {}", day_index_path);
+                    FrameIndex::concat(hourly_indexes).write(&day_index_path);
+                }
+                None => error!("Missing hourly frame indexes, day movie will have none"),
+            }
+            // pins, tags and notes of the hours carry over to the day movie
+            let day_metadata_path = format!(
+                "{}/{}",
+                MOVIES_FOLDER_ROOT,
+                metadata_filename(&folder.timestamp.to_string())
+            );
+            let mut day_metadata = MovieMetadata::read(&day_metadata_path);
+            for movie in &movies {
+                let hour_metadata = MovieMetadata::read(&format!(
+                    "{}/{}",
+                    folder_path,
+                    metadata_filename(&movie.timestamp.to_string())
+                ));
+                let hour_label = Local
+                    .timestamp(movie.timestamp, 0)
+                    .format("%Hh")
+                    .to_string();
+                day_metadata.merge_hour(&hour_label, hour_metadata);
+            }
+            if !day_metadata.is_empty() {
+                info!("Writing day metadata to {}", day_metadata_path);
+                day_metadata.write(&day_metadata_path);
+            }
+            info!("Removing previous today folder!");
+            fs::remove_dir_all(&folder.path)
+                .map_err(|e| format!("Error removing {}: {}", folder_path, e))?;
+            let dest = format!("{}.mp4", &folder.path);
+            info!("Moving result from {} to {}.", out_path, dest);
+            fs::rename(&out_path, &dest)
+                .map_err(|e| format!("Error moving {} to {}: {}", out_path, dest, e))?;
+            self.events.publish(RecorderEvent::StitchFinished {
+                movie: format!("{}.mp4", folder.timestamp),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Message of a panic caught with catch_unwind
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => "panicked".to_string(),
+        },
+    }
+}
@@ -0,0 +1,585 @@
+//! Encoding without ffmpeg, behind the native-encoder feature. The pictures raspistill takes are
+//! already JPEGs, so they are muxed as they are into a QuickTime movie with a MJPEG track:
+//! several times bigger than H.264 and not playable in browsers, but VLC and ffmpeg play it, and
+//! a timelapse keeps being recorded on a Pi without ffmpeg.
+use crate::timelapse::encoder::RESOLUTION;
+use crate::timelapse::{JobRunner, Movie};
+use log::{error, info};
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::process::{Command, Stdio};
+
+/// Whether ffmpeg cannot be run, in which case the native encoder is used
+pub fn ffmpeg_missing() -> bool {
+    let missing = Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_err();
+    if missing {
+        error!("ffmpeg not found, using the native MJPEG encoder");
+    }
+    missing
+}
+
+/// Width and height in the start of frame segment of a JPEG
+fn jpeg_dimensions(jpeg: &[u8]) -> Option<(u16, u16)> {
+    let mut i = 2;
+    while i + 9 < jpeg.len() {
+        if jpeg[i] != 0xFF {
+            return None;
+        }
+        let marker = jpeg[i + 1];
+        let length = u16::from_be_bytes([jpeg[i + 2], jpeg[i + 3]]) as usize;
+        // SOF0 to SOF2, baseline, extended and progressive
+        if (0xC0..=0xC2).contains(&marker) {
+            let height = u16::from_be_bytes([jpeg[i + 5], jpeg[i + 6]]);
+            let width = u16::from_be_bytes([jpeg[i + 7], jpeg[i + 8]]);
+            return Some((width, height));
+        }
+        i += 2 + length;
+    }
+    None
+}
+
+/// A box of the container: its size, type and payload
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut mp4_box = Vec::with_capacity(8 + payload.len());
+    mp4_box.extend(&(8 + payload.len() as u32).to_be_bytes());
+    mp4_box.extend(kind);
+    mp4_box.extend(payload);
+    mp4_box
+}
+
+/// Unity transformation matrix of mvhd and tkhd
+fn unity_matrix() -> Vec<u8> {
+    [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000]
+        .iter()
+        .flat_map(|value| value.to_be_bytes().to_vec())
+        .collect()
+}
+
+/// Writes the frames one by one to the mdat box, and the index of the frames in the moov box
+/// once they are all written
+pub struct MjpegWriter {
+    file: BufWriter<File>,
+    path: String,
+    framerate: u32,
+    /// of the first frame, all frames are expected to share it
+    dimensions: Option<(u16, u16)>,
+    mdat_start: u64,
+    offset: u64,
+    sample_sizes: Vec<u32>,
+    sample_offsets: Vec<u64>,
+}
+
+impl MjpegWriter {
+    pub fn create(path: &str, framerate: u32) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let mut ftyp = b"qt  ".to_vec();
+        ftyp.extend(&0x2005_0300u32.to_be_bytes());
+        ftyp.extend(b"qt  ");
+        let ftyp = mp4_box(b"ftyp", &ftyp);
+        file.write_all(&ftyp)?;
+        let mdat_start = ftyp.len() as u64;
+        // 64 bits size, written once the frames are
+        file.write_all(&1u32.to_be_bytes())?;
+        file.write_all(b"mdat")?;
+        file.write_all(&0u64.to_be_bytes())?;
+        Ok(Self {
+            file,
+            path: path.to_string(),
+            framerate,
+            dimensions: None,
+            mdat_start,
+            offset: mdat_start + 16,
+            sample_sizes: vec![],
+            sample_offsets: vec![],
+        })
+    }
+
+    pub fn push_frame(&mut self, jpeg: &[u8]) -> io::Result<()> {
+        if self.dimensions.is_none() {
+            self.dimensions = jpeg_dimensions(jpeg);
+        }
+        self.file.write_all(jpeg)?;
+        self.sample_sizes.push(jpeg.len() as u32);
+        self.sample_offsets.push(self.offset);
+        self.offset += jpeg.len() as u64;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.sample_sizes.len() as u32
+    }
+
+    /// Writes the index of the frames, the movie is unreadable until then
+    pub fn finish(mut self) -> io::Result<u32> {
+        let frames = self.frames();
+        self.file.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.file
+            .write_all(&(self.offset - self.mdat_start).to_be_bytes())?;
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let moov = self.moov();
+        self.file.write_all(&moov)?;
+        self.file.flush()?;
+        info!("Wrote {} frames to {}", frames, self.path);
+        Ok(frames)
+    }
+
+    fn moov(&self) -> Vec<u8> {
+        let frames = self.frames();
+        let (width, height) = self
+            .dimensions
+            .unwrap_or((RESOLUTION.0 as u16, RESOLUTION.1 as u16));
+        // the movie time scale is in milliseconds, the track one in frames
+        let duration_millis = (frames as u64 * 1000 / self.framerate as u64) as u32;
+        let u32s = |values: &[u32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|v| v.to_be_bytes().to_vec())
+                .collect()
+        };
+
+        let mut mvhd = u32s(&[0, 0, 0, 1000, duration_millis, 0x0001_0000]);
+        mvhd.extend(&0x0100u16.to_be_bytes());
+        mvhd.extend(&[0; 10]);
+        mvhd.extend(unity_matrix());
+        mvhd.extend(&[0; 24]);
+        mvhd.extend(&2u32.to_be_bytes());
+
+        // enabled and in movie
+        let mut tkhd = u32s(&[0x0000_0003, 0, 0, 1, 0, duration_millis, 0, 0]);
+        tkhd.extend(&[0; 8]);
+        tkhd.extend(unity_matrix());
+        tkhd.extend(u32s(&[(width as u32) << 16, (height as u32) << 16]));
+
+        let mut mdhd = u32s(&[0, 0, 0, self.framerate, frames]);
+        // undetermined language
+        mdhd.extend(&[0x55, 0xC4, 0, 0]);
+
+        let mut hdlr = u32s(&[0, 0]);
+        hdlr.extend(b"vide");
+        hdlr.extend(&[0; 12]);
+        hdlr.extend(b"VideoHandler\0");
+
+        let mut vmhd = u32s(&[0x0000_0001]);
+        vmhd.extend(&[0; 8]);
+
+        let mut dref = u32s(&[0, 1]);
+        // the frames are in this file
+        dref.extend(mp4_box(b"url ", &u32s(&[0x0000_0001])));
+
+        let mut jpeg = vec![0; 6];
+        // data reference index
+        jpeg.extend(&1u16.to_be_bytes());
+        jpeg.extend(&[0; 16]);
+        jpeg.extend(&width.to_be_bytes());
+        jpeg.extend(&height.to_be_bytes());
+        // 72 dpi
+        jpeg.extend(u32s(&[0x0048_0000, 0x0048_0000, 0]));
+        jpeg.extend(&1u16.to_be_bytes());
+        let mut compressor = vec![0u8; 32];
+        compressor[0] = 12;
+        compressor[1..13].copy_from_slice(b"Photo - JPEG");
+        jpeg.extend(compressor);
+        jpeg.extend(&24u16.to_be_bytes());
+        jpeg.extend(&0xFFFFu16.to_be_bytes());
+        let mut stsd = u32s(&[0, 1]);
+        stsd.extend(mp4_box(b"jpeg", &jpeg));
+
+        // every frame lasts one unit of the track time scale
+        let stts = u32s(&[0, 1, frames, 1]);
+        // one frame per chunk
+        let stsc = u32s(&[0, 1, 1, 1, 1]);
+        let mut stsz = u32s(&[0, 0, frames]);
+        stsz.extend(u32s(&self.sample_sizes));
+        let mut co64 = u32s(&[0, frames]);
+        for offset in &self.sample_offsets {
+            co64.extend(&offset.to_be_bytes());
+        }
+
+        let stbl = [
+            mp4_box(b"stsd", &stsd),
+            mp4_box(b"stts", &stts),
+            mp4_box(b"stsc", &stsc),
+            mp4_box(b"stsz", &stsz),
+            mp4_box(b"co64", &co64),
+        ]
+        .concat();
+        let minf = [
+            mp4_box(b"vmhd", &vmhd),
+            mp4_box(b"dinf", &mp4_box(b"dref", &dref)),
+            mp4_box(b"stbl", &stbl),
+        ]
+        .concat();
+        let mdia = [
+            mp4_box(b"mdhd", &mdhd),
+            mp4_box(b"hdlr", &hdlr),
+            mp4_box(b"minf", &minf),
+        ]
+        .concat();
+        let trak = [mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat();
+        let moov = [mp4_box(b"mvhd", &mvhd), mp4_box(b"trak", &trak)].concat();
+        mp4_box(b"moov", &moov)
+    }
+}
+
+/// Payload of the first box of the type in the boxes
+fn find_box<'a>(boxes: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut i = 0;
+    while i + 8 <= boxes.len() {
+        let size = u32::from_be_bytes(boxes[i..i + 4].try_into().ok()?) as usize;
+        if size < 8 || i + size > boxes.len() {
+            return None;
+        }
+        if &boxes[i + 4..i + 8] == kind {
+            return Some(&boxes[i + 8..i + size]);
+        }
+        i += size;
+    }
+    None
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Payload of the moov box of a movie, found among the top level boxes, after mdat
+fn read_moov(file: &mut File) -> io::Result<Vec<u8>> {
+    let file_len = file.metadata()?.len();
+    let mut position = 0;
+    loop {
+        if position + 8 > file_len {
+            return Err(invalid("no moov box"));
+        }
+        file.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 16];
+        file.read_exact(&mut header[..8])?;
+        let mut size = u32::from_be_bytes(header[0..4].try_into().expect("4 bytes")) as u64;
+        if size == 1 {
+            file.read_exact(&mut header[8..])?;
+            size = u64::from_be_bytes(header[8..16].try_into().expect("8 bytes"));
+        }
+        if size < 8 || position + size > file_len {
+            return Err(invalid("invalid box size"));
+        }
+        if &header[4..8] == b"moov" {
+            let mut moov = vec![0; size as usize - 8];
+            file.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+        position += size;
+    }
+}
+
+/// Offset and size of each frame of a movie written by MjpegWriter
+fn frame_table(moov: &[u8]) -> io::Result<Vec<(u64, u32)>> {
+    let stbl = find_box(moov, b"trak")
+        .and_then(|trak| find_box(trak, b"mdia"))
+        .and_then(|mdia| find_box(mdia, b"minf"))
+        .and_then(|minf| find_box(minf, b"stbl"))
+        .ok_or_else(|| invalid("no sample table"))?;
+    // the sample description, after its version, flags and count, must be a jpeg one
+    let stsd = find_box(stbl, b"stsd").ok_or_else(|| invalid("no stsd box"))?;
+    if stsd.get(12..16) != Some(b"jpeg") {
+        return Err(invalid("not a MJPEG movie"));
+    }
+    let stsz = find_box(stbl, b"stsz").ok_or_else(|| invalid("no stsz box"))?;
+    let co64 = find_box(stbl, b"co64").ok_or_else(|| invalid("not written by MjpegWriter"))?;
+    if stsz.len() < 12 || co64.len() < 8 {
+        return Err(invalid("truncated sample table"));
+    }
+    let frames = u32::from_be_bytes(stsz[8..12].try_into().expect("4 bytes")) as usize;
+    let offsets = u32::from_be_bytes(co64[4..8].try_into().expect("4 bytes")) as usize;
+    // divided rather than multiplied, the counts are read from the file
+    if offsets != frames || (stsz.len() - 12) / 4 < frames || (co64.len() - 8) / 8 < frames {
+        return Err(invalid("truncated sample table"));
+    }
+    let sizes = stsz[12..].chunks_exact(4);
+    let offsets = co64[8..].chunks_exact(8);
+    Ok(offsets
+        .zip(sizes)
+        .take(frames)
+        .map(|(offset, size)| {
+            (
+                u64::from_be_bytes(offset.try_into().expect("8 bytes")),
+                u32::from_be_bytes(size.try_into().expect("4 bytes")),
+            )
+        })
+        .collect())
+}
+
+/// Reads back the frames of a movie written by MjpegWriter, one at a time
+pub fn for_each_frame(
+    path: &str,
+    mut on_frame: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let moov = read_moov(&mut file)?;
+    for (offset, size) in frame_table(&moov)? {
+        let mut jpeg = vec![0; size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut jpeg)?;
+        on_frame(&jpeg)?;
+    }
+    Ok(())
+}
+
+/// Whether the movie was written by MjpegWriter, and so can be stitched without ffmpeg
+fn is_mjpeg_movie(path: &str) -> bool {
+    File::open(path)
+        .and_then(|mut file| read_moov(&mut file))
+        .and_then(|moov| frame_table(&moov))
+        .is_ok()
+}
+
+/// Checks a movie written by MjpegWriter the way probe::verify does for ffmpeg ones, ffprobe
+/// being missing too: its sample tables are read back, list the frames written and point at
+/// JPEGs inside the file. The error says what is wrong.
+pub fn verify(path: &str, frames: u32) -> Result<(), String> {
+    let checked = (|| {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let moov = read_moov(&mut file)?;
+        let table = frame_table(&moov)?;
+        if table.len() != frames as usize {
+            return Err(invalid(&format!(
+                "{} frames instead of {}",
+                table.len(),
+                frames
+            )));
+        }
+        for (offset, size) in table {
+            if offset + size as u64 > file_len {
+                return Err(invalid("frame past the end of the file"));
+            }
+            let mut start_of_image = [0; 2];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut start_of_image)?;
+            if start_of_image != [0xFF, 0xD8] {
+                return Err(invalid("frame is not a JPEG"));
+            }
+        }
+        Ok(())
+    })();
+    checked.map_err(|e| format!("{} is invalid: {}", path, e))
+}
+
+/// Muxes the pictures of a segment folder, in order, returns the number of frames
+pub fn encode_pictures(img_dir: &str, output_path: &str, framerate: u32) -> io::Result<u32> {
+    let mut pictures: Vec<String> = fs::read_dir(img_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".jpg"))
+        .collect();
+    // named by frame number, %05d.jpg
+    pictures.sort();
+    let mut writer = MjpegWriter::create(output_path, framerate)?;
+    for picture in pictures {
+        writer.push_frame(&fs::read(format!("{}/{}", img_dir, picture))?)?;
+    }
+    writer.finish()
+}
+
+impl JobRunner {
+    /// Concatenates the hourly clips written by MjpegWriter, None if ffmpeg can stitch them. A
+    /// day with clips encoded by ffmpeg, before it went missing, fails without writing anything.
+    pub(super) fn stitch_natively(
+        &self,
+        movies: &[Movie],
+        output_path: &str,
+        framerate: u32,
+    ) -> Option<Result<(), String>> {
+        if !ffmpeg_missing() {
+            return None;
+        }
+        if let Some(movie) = movies.iter().find(|movie| !is_mjpeg_movie(&movie.path)) {
+            return Some(Err(format!(
+                "{} was not written by the native encoder, stitching it needs ffmpeg",
+                movie.path
+            )));
+        }
+        let stitched = (|| {
+            let mut writer = MjpegWriter::create(output_path, framerate)?;
+            for movie in movies {
+                for_each_frame(&movie.path, |jpeg| writer.push_frame(jpeg))?;
+            }
+            writer.finish()
+        })();
+        Some(
+            stitched
+                .map_err(|e| format!("native stitching failed: {}", e))
+                .and_then(|frames| verify(output_path, frames)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start of image, a baseline start of frame and some scan bytes, enough for the muxer
+    fn synthetic_jpeg(width: u16, height: u16, seed: u8) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x11, 8];
+        jpeg.extend(&height.to_be_bytes());
+        jpeg.extend(&width.to_be_bytes());
+        jpeg.extend(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+        jpeg.extend((0..seed as usize * 7 + 3).map(|i| (i as u8).wrapping_mul(seed)));
+        jpeg.extend(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    fn read_moov_of(path: &str) -> Vec<u8> {
+        read_moov(&mut File::open(path).unwrap()).unwrap()
+    }
+
+    /// Timescale and duration of a mvhd or mdhd payload
+    fn timing(header: &[u8]) -> (u32, u32) {
+        (
+            u32::from_be_bytes(header[12..16].try_into().unwrap()),
+            u32::from_be_bytes(header[16..20].try_into().unwrap()),
+        )
+    }
+
+    #[test]
+    fn dimensions_are_read_from_the_start_of_frame() {
+        assert_eq!(
+            jpeg_dimensions(&synthetic_jpeg(1640, 1232, 1)),
+            Some((1640, 1232))
+        );
+        // an APP0 segment before the start of frame is skipped
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0, 0];
+        jpeg.extend(&synthetic_jpeg(320, 240, 1)[2..]);
+        assert_eq!(jpeg_dimensions(&jpeg), Some((320, 240)));
+        assert_eq!(jpeg_dimensions(&[0xFF, 0xD8, 0x00, 0x00]), None);
+        assert_eq!(
+            jpeg_dimensions(&[0xFF, 0xD8, 0x12, 0, 0, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("mjpeg_round_trip_{}.mov", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let frames: Vec<Vec<u8>> = (1..=25).map(|seed| synthetic_jpeg(64, 48, seed)).collect();
+        let mut writer = MjpegWriter::create(&path, 10).unwrap();
+        for frame in &frames {
+            writer.push_frame(frame).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 25);
+
+        let mut read = vec![];
+        for_each_frame(&path, |jpeg| {
+            read.push(jpeg.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        let moov = read_moov_of(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read, frames);
+        // 25 frames at 10 per second, in milliseconds for the movie and frames for the track
+        assert_eq!(timing(find_box(&moov, b"mvhd").unwrap()), (1000, 2500));
+        let mdhd = find_box(&moov, b"trak")
+            .and_then(|trak| find_box(trak, b"mdia"))
+            .and_then(|mdia| find_box(mdia, b"mdhd"))
+            .unwrap();
+        assert_eq!(timing(mdhd), (10, 25));
+    }
+
+    #[test]
+    fn truncated_sample_tables_are_rejected() {
+        let path = std::env::temp_dir()
+            .join(format!("mjpeg_truncated_{}.mov", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut writer = MjpegWriter::create(&path, 10).unwrap();
+        writer.push_frame(&synthetic_jpeg(64, 48, 1)).unwrap();
+        writer.finish().unwrap();
+        let moov = read_moov_of(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(frame_table(&moov).unwrap().len(), 1);
+
+        let with_stbl = |stbl: &[u8]| {
+            let minf = mp4_box(b"minf", &mp4_box(b"stbl", stbl));
+            mp4_box(b"trak", &mp4_box(b"mdia", &minf))
+        };
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(mp4_box(b"jpeg", &[]));
+        let stsd = mp4_box(b"stsd", &stsd);
+        // boxes too short for their header
+        let short = [
+            stsd.clone(),
+            mp4_box(b"stsz", &[0; 4]),
+            mp4_box(b"co64", &[0; 4]),
+        ]
+        .concat();
+        assert!(frame_table(&with_stbl(&short)).is_err());
+        // more frames announced than listed
+        let mut stsz = vec![0; 8];
+        stsz.extend(&u32::MAX.to_be_bytes());
+        let mut co64 = vec![0; 4];
+        co64.extend(&u32::MAX.to_be_bytes());
+        let overflowing = [stsd, mp4_box(b"stsz", &stsz), mp4_box(b"co64", &co64)].concat();
+        assert!(frame_table(&with_stbl(&overflowing)).is_err());
+    }
+
+    #[test]
+    fn movies_not_muxed_natively_are_detected() {
+        let path = std::env::temp_dir()
+            .join(format!("mjpeg_detected_{}.mov", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut writer = MjpegWriter::create(&path, 10).unwrap();
+        writer.push_frame(&synthetic_jpeg(64, 48, 1)).unwrap();
+        writer.finish().unwrap();
+        let native = is_mjpeg_movie(&path);
+        // what ffmpeg writes for H.264, a sample description of another format and 32 bits
+        // chunk offsets
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(mp4_box(b"avc1", &[]));
+        let stbl = [
+            mp4_box(b"stsd", &stsd),
+            mp4_box(b"stsz", &[0; 12]),
+            mp4_box(b"stco", &[0; 8]),
+        ]
+        .concat();
+        let minf = mp4_box(b"minf", &mp4_box(b"stbl", &stbl));
+        let moov = mp4_box(b"moov", &mp4_box(b"trak", &mp4_box(b"mdia", &minf)));
+        fs::write(&path, [mp4_box(b"ftyp", b"isom"), moov].concat()).unwrap();
+        let h264 = is_mjpeg_movie(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(native);
+        assert!(!h264);
+    }
+
+    #[test]
+    fn verification_reads_back_the_frames() {
+        let path = std::env::temp_dir()
+            .join(format!("mjpeg_verified_{}.mov", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let mut writer = MjpegWriter::create(&path, 10).unwrap();
+        for seed in 1..=3 {
+            writer.push_frame(&synthetic_jpeg(64, 48, seed)).unwrap();
+        }
+        let frames = writer.finish().unwrap();
+        let valid = verify(&path, frames);
+        let missing_frame = verify(&path, frames + 1);
+        // the last frame overwritten, as a failing disk would
+        let (offset, _) = *frame_table(&read_moov_of(&path)).unwrap().last().unwrap();
+        let mut movie = fs::read(&path).unwrap();
+        movie[offset as usize] = 0;
+        fs::write(&path, movie).unwrap();
+        let corrupted = verify(&path, frames);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(valid, Ok(()));
+        assert!(missing_frame.unwrap_err().contains("3 frames instead of 4"));
+        assert!(corrupted.unwrap_err().contains("not a JPEG"));
+    }
+}
@@ -0,0 +1,146 @@
+use chrono::Local;
+use log::{error, info};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Outputs failing verification are moved here, with a .quarantine.json saying why, instead of
+/// replacing anything
+pub const QUARANTINE_FOLDER: &str = "/mnt/skynet/quarantine";
+/// Slack between the probed duration and the expected one, a frame is 100ms
+const MAX_DURATION_DIFFERENCE_SECS: f64 = 1.0;
+
+/// What ffprobe tells about a movie
+#[derive(Debug)]
+pub struct Probe {
+    pub duration_secs: f64,
+    /// of the first video stream, as named by ffprobe: h264, hevc, vp9, av1
+    pub codec: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+}
+
+/// What an output must look like to replace its inputs, unset fields are not checked
+#[derive(Clone, Debug, Default)]
+pub struct Expected {
+    pub duration_secs: Option<f64>,
+    pub codec: Option<String>,
+    pub resolution: Option<(u64, u64)>,
+}
+
+/// Written next to a quarantined movie
+#[derive(Serialize)]
+struct Quarantined<'a> {
+    movie: &'a str,
+    reason: &'a str,
+    quarantined_at: i64,
+}
+
+/// Codec ffprobe reports for the movies of an ffmpeg encoder, None if unknown
+pub fn probed_codec(encoder: &str) -> Option<String> {
+    let codec = if encoder.contains("264") {
+        "h264"
+    } else if encoder.contains("265") || encoder.contains("hevc") {
+        "hevc"
+    } else if encoder.contains("vp9") {
+        "vp9"
+    } else if encoder.contains("av1") {
+        "av1"
+    } else {
+        return None;
+    };
+    Some(codec.to_string())
+}
+
+/// None if ffprobe cannot read the container
+pub fn probe(path: &str) -> Option<Probe> {
+    // ffprobe -v error -select_streams v:0 -show_entries format=duration:stream=codec_name,width,height -of json
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("format=duration:stream=codec_name,width,height")
+        .arg("-of")
+        .arg("json")
+        .arg(path)
+        .output()
+        .map_err(|e| error!("Error running ffprobe on {}: {}", path, e))
+        .ok()?;
+    if !output.status.success() {
+        error!(
+            "ffprobe failed on {}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return None;
+    }
+    let probe: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    let stream = &probe["streams"][0];
+    Some(Probe {
+        // ffprobe writes the duration as a string
+        duration_secs: probe["format"]["duration"].as_str()?.parse().ok()?,
+        codec: stream["codec_name"].as_str().map(|codec| codec.to_string()),
+        width: stream["width"].as_u64(),
+        height: stream["height"].as_u64(),
+    })
+}
+
+/// Checks the movie is readable and looks as expected, the error says what is wrong
+pub fn verify(path: &str, expected: &Expected) -> Result<Probe, String> {
+    let probe = probe(path).ok_or_else(|| format!("{} cannot be probed", path))?;
+    if probe.codec.is_none() {
+        return Err(format!("{} has no video stream", path));
+    }
+    if let Some(duration_secs) = expected.duration_secs {
+        if (probe.duration_secs - duration_secs).abs() > MAX_DURATION_DIFFERENCE_SECS {
+            return Err(format!(
+                "{} lasts {}s instead of {}s",
+                path, probe.duration_secs, duration_secs
+            ));
+        }
+    }
+    if expected.codec.is_some() && probe.codec != expected.codec {
+        return Err(format!(
+            "{} is {:?} instead of {:?}",
+            path, probe.codec, expected.codec
+        ));
+    }
+    if let Some((width, height)) = expected.resolution {
+        if probe.width != Some(width) || probe.height != Some(height) {
+            return Err(format!(
+                "{} is {:?}x{:?} instead of {}x{}",
+                path, probe.width, probe.height, width, height
+            ));
+        }
+    }
+    Ok(probe)
+}
+
+/// Moves a movie that failed verification to QUARANTINE_FOLDER
+pub fn quarantine(path: &str, reason: &str) {
+    let name = Path::new(path)
+        .file_name()
+        .expect("Quarantined path has a file name")
+        .to_string_lossy()
+        .to_string();
+    let dest = format!("{}/{}", QUARANTINE_FOLDER, name);
+    error!("Quarantining {} to {}: {}", path, dest, reason);
+    if let Err(e) = fs::create_dir_all(QUARANTINE_FOLDER).and_then(|_| fs::rename(path, &dest)) {
+        error!("Error moving {} to {}: {}", path, dest, e);
+        return;
+    }
+    let quarantined = Quarantined {
+        movie: &name,
+        reason,
+        quarantined_at: Local::now().timestamp(),
+    };
+    let json = serde_json::to_string_pretty(&quarantined).expect("Error serializing quarantine");
+    let json_path = format!("{}.quarantine.json", dest);
+    if let Err(e) = fs::write(&json_path, json) {
+        error!("Error writing {}: {}", json_path, e);
+    }
+    info!("Quarantined {}", dest);
+}
@@ -0,0 +1,91 @@
+use crate::timelapse::encoder::FRAMERATE;
+use chrono::prelude::*;
+use std::fs;
+
+/// Same format raspistill burns into the pictures
+const CAPTURE_TIME_FORMAT: &str = "%d-%m-%Y %X";
+
+fn format_srt_time(millis: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        (millis / 60_000) % 60,
+        (millis / 1000) % 60,
+        millis % 1000
+    )
+}
+
+/// SRT subtitle track showing the capture time of each output frame.
+/// Consecutive frames showing the same text share a single cue.
+fn capture_time_srt(capture_times: &[DateTime<Local>]) -> String {
+    let frame_millis = |frame: usize| frame as u64 * 1000 / FRAMERATE as u64;
+    let mut srt = String::new();
+    let mut cue = 1;
+    let mut cue_start = 0;
+    for frame in 0..capture_times.len() {
+        let text = capture_times[frame].format(CAPTURE_TIME_FORMAT).to_string();
+        let is_last_of_cue = match capture_times.get(frame + 1) {
+            Some(next) => next.format(CAPTURE_TIME_FORMAT).to_string() != text,
+            None => true,
+        };
+        if is_last_of_cue {
+            srt.push_str(&format!(
+                "{}\n{} --> {}\n{}\n\n",
+                cue,
+                format_srt_time(frame_millis(cue_start)),
+                format_srt_time(frame_millis(frame + 1)),
+                text
+            ));
+            cue += 1;
+            cue_start = frame + 1;
+        }
+    }
+    srt
+}
+
+pub fn write_capture_time_srt(capture_times: &[DateTime<Local>], output_path: &str) {
+    let srt = capture_time_srt(capture_times);
+    fs::write(output_path, srt).expect(&format!("Error writing {}", output_path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srt_time_format() {
+        assert_eq!(format_srt_time(0), "00:00:00,000");
+        assert_eq!(format_srt_time(3_723_045), "01:02:03,045");
+    }
+
+    #[test]
+    fn frames_of_the_same_second_share_a_cue() {
+        let second = Local.ymd(2021, 3, 4).and_hms(12, 30, 0);
+        let capture_times = vec![
+            second,
+            second + chrono::Duration::milliseconds(400),
+            second + chrono::Duration::milliseconds(800),
+            second + chrono::Duration::seconds(1),
+            second + chrono::Duration::seconds(5),
+        ];
+        let frame_millis = 1000 / FRAMERATE as u64;
+        assert_eq!(
+            capture_time_srt(&capture_times),
+            format!(
+                "1\n00:00:00,000 --> {}\n04-03-2021 12:30:00\n\n\
+                 2\n{} --> {}\n04-03-2021 12:30:01\n\n\
+                 3\n{} --> {}\n04-03-2021 12:30:05\n\n",
+                format_srt_time(3 * frame_millis),
+                format_srt_time(3 * frame_millis),
+                format_srt_time(4 * frame_millis),
+                format_srt_time(4 * frame_millis),
+                format_srt_time(5 * frame_millis),
+            )
+        );
+    }
+
+    #[test]
+    fn no_frames_no_cues() {
+        assert_eq!(capture_time_srt(&[]), "");
+    }
+}
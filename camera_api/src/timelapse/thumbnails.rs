@@ -0,0 +1,274 @@
+use crate::timelapse::encoder::FRAMERATE;
+use log::{error, info};
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// One thumbnail is sampled for every THUMBNAIL_INTERVAL_SECS of output video
+pub const THUMBNAIL_INTERVAL_SECS: u32 = 5;
+const THUMBNAIL_WIDTH: u32 = 160;
+const THUMBNAIL_HEIGHT: u32 = 120;
+const SPRITE_COLUMNS: u32 = 10;
+
+/// Sprite image sidecar of a movie, stored next to it: 1234.mp4 -> 1234.thumbs.jpg
+pub fn sprite_filename(movie_stem: &str) -> String {
+    format!("{}.thumbs.jpg", movie_stem)
+}
+
+/// WebVTT thumbnail track sidecar of a movie, stored next to it: 1234.mp4 -> 1234.thumbs.vtt
+pub fn vtt_filename(movie_stem: &str) -> String {
+    format!("{}.thumbs.vtt", movie_stem)
+}
+
+fn count_frames(img_dir: &str) -> u32 {
+    fs::read_dir(img_dir)
+        .expect(&format!("Error reading pics dir {}", img_dir))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".jpg"))
+        .count() as u32
+}
+
+fn format_vtt_time(millis: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        (millis / 60_000) % 60,
+        (millis / 1000) % 60,
+        millis % 1000
+    )
+}
+
+fn parse_vtt_time(time: &str) -> Option<u64> {
+    let (hms, millis) = time.trim().split_at(time.trim().find('.')?);
+    let parts: Vec<u64> = hms
+        .split(':')
+        .map(|p| p.parse::<u64>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    if parts.len() != 3 {
+        return None;
+    }
+    let millis: u64 = millis[1..].parse().ok()?;
+    Some(parts[0] * 3_600_000 + parts[1] * 60_000 + parts[2] * 1000 + millis)
+}
+
+/// Builds a tiled sprite of the frames in img_dir (sampled every THUMBNAIL_INTERVAL_SECS of
+/// output) plus the WebVTT track mapping time ranges to sprite coordinates.
+/// Both are written to output_dir, named after movie_stem.
+pub fn generate_thumbnails(img_dir: &str, output_dir: &str, movie_stem: &str) {
+    let frames = count_frames(img_dir);
+    if frames == 0 {
+        error!("No frames in {}, not generating thumbnails", img_dir);
+        return;
+    }
+    let frames_per_thumbnail = FRAMERATE * THUMBNAIL_INTERVAL_SECS;
+    let thumbnails = (frames + frames_per_thumbnail - 1) / frames_per_thumbnail;
+    let rows = (thumbnails + SPRITE_COLUMNS - 1) / SPRITE_COLUMNS;
+    let sprite_filename = sprite_filename(movie_stem);
+    let sprite_path = format!("{}/{}", output_dir, sprite_filename);
+    info!(
+        "Generating {} thumbnails sprite of {} frames at {}",
+        thumbnails, frames, sprite_path
+    );
+    // ffmpeg -i %05d.jpg -vf "select='not(mod(n\,50))',scale=160:120,tile=10x6" -frames:v 1 out.jpg
+    let output = Command::new("ffmpeg")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .arg("-y")
+        .arg("-framerate")
+        .arg(format!("{}", FRAMERATE))
+        .arg("-i")
+        .arg(format!("{}/%05d.jpg", img_dir))
+        .arg("-vf")
+        .arg(format!(
+            "select='not(mod(n\\,{}))',scale={}:{},tile={}x{}",
+            frames_per_thumbnail, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, SPRITE_COLUMNS, rows
+        ))
+        .arg("-vsync")
+        .arg("vfr")
+        .arg("-frames:v")
+        .arg("1")
+        .arg("-q:v")
+        .arg("5")
+        .arg(&sprite_path)
+        .output();
+    // no thumbnails rather than no movie, with the native encoder ffmpeg may be missing
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Thumbnail sprite command failed to start: {}", e);
+            return;
+        }
+    };
+    if !output.status.success() {
+        error!("Thumbnail sprite process did not end successfully");
+        error!("{}", String::from_utf8_lossy(&output.stderr));
+        return;
+    }
+
+    let movie_duration_millis = frames as u64 * 1000 / FRAMERATE as u64;
+    let mut vtt = String::from("WEBVTT\n\n");
+    for thumbnail in 0..thumbnails {
+        let start = thumbnail as u64 * THUMBNAIL_INTERVAL_SECS as u64 * 1000;
+        let end = std::cmp::min(
+            start + THUMBNAIL_INTERVAL_SECS as u64 * 1000,
+            movie_duration_millis,
+        );
+        vtt.push_str(&format!(
+            "{} --> {}\n{}#xywh={},{},{},{}\n\n",
+            format_vtt_time(start),
+            format_vtt_time(end),
+            sprite_filename,
+            (thumbnail % SPRITE_COLUMNS) * THUMBNAIL_WIDTH,
+            (thumbnail / SPRITE_COLUMNS) * THUMBNAIL_HEIGHT,
+            THUMBNAIL_WIDTH,
+            THUMBNAIL_HEIGHT
+        ));
+    }
+    let vtt_path = format!("{}/{}", output_dir, vtt_filename(movie_stem));
+    fs::write(&vtt_path, vtt).expect(&format!("Error writing {}", vtt_path));
+}
+
+/// Appends the cues of an hourly track to the day track, shifted by the offset. Returns the end
+/// of the last cue, the duration of the hour.
+fn append_shifted_cues(day_vtt: &mut String, hourly_vtt: &str, offset: u64) -> u64 {
+    let mut hour_end = 0;
+    for cue in hourly_vtt.split("\n\n").skip(1) {
+        let mut lines = cue.lines();
+        let (timing, payload) = match (lines.next(), lines.next()) {
+            (Some(timing), Some(payload)) => (timing, payload),
+            _ => continue,
+        };
+        let mut times = timing.split("-->").filter_map(parse_vtt_time);
+        if let (Some(start), Some(end)) = (times.next(), times.next()) {
+            day_vtt.push_str(&format!(
+                "{} --> {}\n{}\n\n",
+                format_vtt_time(start + offset),
+                format_vtt_time(end + offset),
+                payload
+            ));
+            hour_end = end;
+        }
+    }
+    hour_end
+}
+
+/// Concatenates the WebVTT tracks of the hourly movies (in playback order) into a single track
+/// for the stitched day movie, shifting each hour's cues by the duration of the previous hours.
+/// The cues keep pointing to the hourly sprites, which must be moved next to the day track.
+/// An hour without track has no cues, the following ones are shifted by its duration from
+/// hour_duration_millis, given its index. When that is unknown too no day track is written, the
+/// cues of the following hours would be off.
+pub fn stitch_vtt(
+    hourly_vtt_paths: &[String],
+    hour_duration_millis: impl Fn(usize) -> Option<u64>,
+    output_path: &str,
+) {
+    let mut day_vtt = String::from("WEBVTT\n\n");
+    let mut offset = 0;
+    for (hour, path) in hourly_vtt_paths.iter().enumerate() {
+        match fs::read_to_string(path) {
+            Ok(hourly_vtt) => offset += append_shifted_cues(&mut day_vtt, &hourly_vtt, offset),
+            Err(e) => {
+                error!("Could not read thumbnails track {}: {}", path, e);
+                match hour_duration_millis(hour) {
+                    Some(duration) => offset += duration,
+                    None => {
+                        error!("Unknown duration of the hour, not writing {}", output_path);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+    fs::write(output_path, day_vtt).expect(&format!("Error writing {}", output_path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_VTT: &str = "WEBVTT\n\n\
+        00:00:00.000 --> 00:00:05.000\n1.thumbs.jpg#xywh=0,0,160,120\n\n\
+        00:00:05.000 --> 00:00:07.300\n1.thumbs.jpg#xywh=160,0,160,120\n\n";
+
+    #[test]
+    fn vtt_time_round_trips() {
+        let millis = 3_600_000 + 2 * 60_000 + 3_000 + 45;
+        assert_eq!(format_vtt_time(millis), "01:02:03.045");
+        assert_eq!(parse_vtt_time(" 01:02:03.045 "), Some(millis));
+    }
+
+    #[test]
+    fn invalid_vtt_times_are_rejected() {
+        assert_eq!(parse_vtt_time("01:02:03"), None);
+        assert_eq!(parse_vtt_time("02:03.045"), None);
+        assert_eq!(parse_vtt_time("aa:02:03.045"), None);
+    }
+
+    #[test]
+    fn cues_are_shifted_by_the_offset() {
+        let mut day_vtt = String::new();
+        let hour_end = append_shifted_cues(&mut day_vtt, HOUR_VTT, 60_000);
+        assert_eq!(hour_end, 7_300);
+        assert_eq!(
+            day_vtt,
+            "00:01:00.000 --> 00:01:05.000\n1.thumbs.jpg#xywh=0,0,160,120\n\n\
+             00:01:05.000 --> 00:01:07.300\n1.thumbs.jpg#xywh=160,0,160,120\n\n"
+        );
+    }
+
+    #[test]
+    fn malformed_cues_are_skipped() {
+        let hourly_vtt = "WEBVTT\n\nnot a timing\npayload\n\n00:00:01.000\n\n\
+            00:00:00.000 --> 00:00:05.000\nsprite.jpg#xywh=0,0,160,120\n\n";
+        let mut day_vtt = String::new();
+        assert_eq!(append_shifted_cues(&mut day_vtt, hourly_vtt, 0), 5_000);
+        assert_eq!(
+            day_vtt,
+            "00:00:00.000 --> 00:00:05.000\nsprite.jpg#xywh=0,0,160,120\n\n"
+        );
+    }
+
+    #[test]
+    fn stitched_track_follows_the_hours() {
+        let dir = std::env::temp_dir().join(format!("stitch_vtt_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hour_path = dir.join("1.thumbs.vtt").to_string_lossy().to_string();
+        let day_path = dir.join("day.thumbs.vtt").to_string_lossy().to_string();
+        fs::write(&hour_path, HOUR_VTT).unwrap();
+        let missing_path = dir.join("missing.vtt").to_string_lossy().to_string();
+        let hours = [hour_path.clone(), missing_path, hour_path];
+        // the hour without track lasts 10 seconds
+        stitch_vtt(&hours, |_| Some(10_000), &day_path);
+        let day_vtt = fs::read_to_string(&day_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(day_vtt.starts_with("WEBVTT\n\n"));
+        let timings: Vec<&str> = day_vtt
+            .lines()
+            .filter(|line| line.contains("-->"))
+            .collect();
+        assert_eq!(
+            timings,
+            vec![
+                "00:00:00.000 --> 00:00:05.000",
+                "00:00:05.000 --> 00:00:07.300",
+                "00:00:17.300 --> 00:00:22.300",
+                "00:00:22.300 --> 00:00:24.600",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_track_when_an_hour_duration_is_unknown() {
+        let dir = std::env::temp_dir().join(format!("stitch_vtt_unknown_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let hour_path = dir.join("1.thumbs.vtt").to_string_lossy().to_string();
+        let day_path = dir.join("day.thumbs.vtt").to_string_lossy().to_string();
+        fs::write(&hour_path, HOUR_VTT).unwrap();
+        let missing_path = dir.join("missing.vtt").to_string_lossy().to_string();
+        stitch_vtt(&[missing_path, hour_path], |_| None, &day_path);
+        let written = fs::metadata(&day_path).is_ok();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!written);
+    }
+}
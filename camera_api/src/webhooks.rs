@@ -0,0 +1,265 @@
+use crate::config::WebhooksConfig;
+use crate::events::RecorderEvent;
+use crossbeam_channel::{Sender, TrySendError};
+use hmac::{Hmac, Mac, NewMac};
+use log::{error, info};
+use serde::Serialize;
+use sha2::Sha256;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Too frequent to be worth a notification, sent only if explicitly listed in the config. Every
+/// event sent for each frame, or more often than each hour, must be listed.
+const NOISY_EVENTS: [&str; 5] = [
+    "frame_captured",
+    "segment_started",
+    "segment_ended",
+    "encode_started",
+    "encode_progress",
+];
+/// Events waiting for delivery beyond which new ones are dropped, while an endpoint is down
+const QUEUE_CAPACITY: usize = 100;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT_SECS: u32 = 10;
+/// Size past which the delivery log is moved to <delivery_log>.1, replacing the previous one
+const MAX_DELIVERY_LOG_BYTES: u64 = 1024 * 1024;
+
+/// Line of the delivery log
+#[derive(Serialize)]
+struct DeliveryAttempt<'a> {
+    time: String,
+    url: &'a str,
+    event: &'a str,
+    attempt: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    delivered: bool,
+}
+
+/// Queue of the thread POSTing the events to the webhooks. Deliveries are retried with
+/// exponential backoff in that thread, the recorder never waits.
+#[derive(Clone, Debug)]
+pub struct Webhooks {
+    sender: Sender<RecorderEvent>,
+    /// event types to send, see WebhooksConfig
+    events: Vec<String>,
+}
+
+impl Webhooks {
+    pub fn start(config: WebhooksConfig) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded::<RecorderEvent>(QUEUE_CAPACITY);
+        info!("Sending events to {} webhooks", config.urls.len());
+        let events = config.events.clone();
+        std::thread::spawn(move || {
+            for event in receiver {
+                let body = serde_json::to_vec(&event).expect("Error serializing recorder event");
+                for url in &config.urls {
+                    deliver(&config, url, event.event_type(), &body);
+                }
+            }
+        });
+        Self { sender, events }
+    }
+
+    fn wants(&self, event_type: &str) -> bool {
+        if self.events.is_empty() {
+            !NOISY_EVENTS.contains(&event_type)
+        } else {
+            self.events.iter().any(|e| e == event_type)
+        }
+    }
+
+    /// Queues the event if it is wanted, dropping it when the queue is full
+    pub fn send(&self, event: &RecorderEvent) {
+        if !self.wants(event.event_type()) {
+            return;
+        }
+        match self.sender.try_send(event.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => error!(
+                "Webhooks are {} events behind, dropping {}",
+                QUEUE_CAPACITY,
+                event.event_type()
+            ),
+            Err(TrySendError::Disconnected(event)) => {
+                error!("Webhooks thread is gone, dropping {}", event.event_type())
+            }
+        }
+    }
+}
+
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn deliver(config: &WebhooksConfig, url: &str, event_type: &str, body: &[u8]) {
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=config.max_attempts {
+        let (status, error) = match post(config, url, event_type, body) {
+            Ok(status) => (Some(status), None),
+            Err(e) => (None, Some(e)),
+        };
+        let delivered = status.map_or(false, |status| (200..300).contains(&status));
+        log_attempt(
+            &config.delivery_log,
+            &DeliveryAttempt {
+                time: chrono::Local::now().to_rfc3339(),
+                url,
+                event: event_type,
+                attempt,
+                status,
+                error,
+                delivered,
+            },
+        );
+        if delivered {
+            return;
+        }
+        if attempt < config.max_attempts {
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+    error!(
+        "Giving up delivering {} to {} after {} attempts",
+        event_type, url, config.max_attempts
+    );
+}
+
+/// POSTs the body with curl, returning the HTTP status code
+fn post(config: &WebhooksConfig, url: &str, event_type: &str, body: &[u8]) -> Result<u16, String> {
+    let mut command = Command::new("curl");
+    command
+        .arg("--silent")
+        .arg("--show-error")
+        .arg("--output")
+        .arg("/dev/null")
+        .arg("--write-out")
+        .arg("%{http_code}")
+        .arg("--max-time")
+        .arg(REQUEST_TIMEOUT_SECS.to_string())
+        .arg("-X")
+        .arg("POST")
+        .arg("-H")
+        .arg("Content-Type: application/json")
+        .arg("-H")
+        .arg(format!("X-Timelapse-Event: {}", event_type));
+    if let Some(secret) = &config.secret {
+        command.arg("-H").arg(format!(
+            "X-Timelapse-Signature: {}",
+            signature(secret, body)
+        ));
+    }
+    let mut process = command
+        .arg("--data-binary")
+        .arg("@-")
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not start curl: {}", e))?;
+    process
+        .stdin
+        .take()
+        .expect("curl stdin is piped")
+        .write_all(body)
+        .map_err(|e| format!("Error writing body to curl: {}", e))?;
+    let output = process
+        .wait_with_output()
+        .map_err(|e| format!("Error waiting for curl: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| "curl did not report a status code".to_string())
+}
+
+fn log_attempt(delivery_log: &str, attempt: &DeliveryAttempt) {
+    let line = serde_json::to_string(attempt).expect("Error serializing delivery attempt");
+    let full = fs::metadata(delivery_log)
+        .map(|log| log.len() >= MAX_DELIVERY_LOG_BYTES)
+        .unwrap_or(false);
+    if full {
+        let rotated = format!("{}.1", delivery_log);
+        if let Err(e) = fs::rename(delivery_log, &rotated) {
+            error!("Error moving {} to {}: {}", delivery_log, rotated, e);
+        }
+    }
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(delivery_log)
+        .and_then(|mut log| writeln!(log, "{}", line));
+    if let Err(e) = written {
+        error!("Could not write to delivery log {}: {}", delivery_log, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhooks(events: &[&str]) -> Webhooks {
+        Webhooks {
+            sender: crossbeam_channel::bounded(1).0,
+            events: events.iter().map(|event| event.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn frequent_events_are_not_sent_by_default() {
+        let webhooks = webhooks(&[]);
+        for event in &["frame_captured", "encode_progress", "segment_started"] {
+            assert!(!webhooks.wants(event), "{} is sent", event);
+        }
+        for event in &[
+            "capture_error",
+            "encode_failed",
+            "stitch_failed",
+            "disk_low",
+        ] {
+            assert!(webhooks.wants(event), "{} is not sent", event);
+        }
+    }
+
+    #[test]
+    fn listed_events_only_are_sent() {
+        let webhooks = webhooks(&["frame_captured"]);
+        assert!(webhooks.wants("frame_captured"));
+        assert!(!webhooks.wants("capture_error"));
+    }
+
+    #[test]
+    fn delivery_log_is_rotated() {
+        let dir = std::env::temp_dir().join(format!("delivery_log_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("deliveries.jsonl").to_string_lossy().to_string();
+        fs::write(&log, vec![b'\n'; MAX_DELIVERY_LOG_BYTES as usize]).unwrap();
+        let attempt = DeliveryAttempt {
+            time: "2021-03-04T12:30:00+01:00".to_string(),
+            url: "http://localhost/hook",
+            event: "disk_low",
+            attempt: 1,
+            status: Some(204),
+            error: None,
+            delivered: true,
+        };
+        log_attempt(&log, &attempt);
+        let current = fs::read_to_string(&log).unwrap();
+        let rotated = fs::metadata(format!("{}.1", log)).unwrap().len();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains("\"delivered\":true"));
+        assert_eq!(rotated, MAX_DELIVERY_LOG_BYTES);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::catalog::{CatalogMovie, MovieKind};
+use crate::errors::ApiError;
+use hmac::{Hmac, Mac, NewMac};
+use log::{error, info};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::sync::Mutex;
+
+/// Prefix of the password hashes, the only scheme supported
+const PASSWORD_HASH_SCHEME: &str = "pbkdf2-sha256";
+/// Rounds of the hashes printed by hash-password, about 0.1s on a Pi 3
+const PASSWORD_HASH_ROUNDS: u32 = 50_000;
+const PASSWORD_SALT_BYTES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// watch the hourly clips of the current day
+    Live,
+    /// watch the stitched movies of past days
+    Archive,
+    /// manage the archive, implies every other scope
+    Admin,
+}
+
+/// Hourly clips of the current day are live footage, stitched days are the archive
+pub fn movie_scope(movie: &CatalogMovie) -> Scope {
+    match movie.kind {
+        MovieKind::Hourly => Scope::Live,
+        MovieKind::Daily => Scope::Archive,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenConfig {
+    name: String,
+    /// hex encoded sha256 of the bearer token, which is random and long, unlike passwords
+    token_sha256: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserConfig {
+    username: String,
+    /// salted PBKDF2 of the password, for HTTP Basic authentication, as printed by
+    /// `video_streaming_api hash-password`: pbkdf2-sha256$<rounds>$<hex salt>$<hex hash>
+    password_hash: String,
+    scopes: Vec<Scope>,
+}
+
+/// Read at startup from the file set by the auth_file Rocket config extra,
+/// see auth.example.json
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// key of the HMAC signing the expiring /stream, /thumbnails and /events URLs
+    signing_secret: String,
+    /// origins allowed by CORS, no cross origin request is allowed if empty
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    users: Vec<UserConfig>,
+    /// sha256 of the username:password pairs already checked, Basic authentication sends them
+    /// with every request and deriving the hash each time would slow every request down
+    #[serde(skip)]
+    verified_credentials: Mutex<HashSet<String>>,
+}
+
+fn sha256_hex(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+fn pbkdf2_sha256(password: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mut hash = [0; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, rounds, &mut hash);
+    hash
+}
+
+/// Hash to store in auth.json for the password, with a random salt
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0; PASSWORD_SALT_BYTES];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut salt))
+        .expect("Error reading /dev/urandom");
+    format!(
+        "{}${}${}${}",
+        PASSWORD_HASH_SCHEME,
+        PASSWORD_HASH_ROUNDS,
+        hex::encode(salt),
+        hex::encode(pbkdf2_sha256(password, &salt, PASSWORD_HASH_ROUNDS))
+    )
+}
+
+/// False if the hash is not a valid pbkdf2-sha256 one
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    let fields: Vec<&str> = password_hash.split('$').collect();
+    let (rounds, salt, hash) = match fields.as_slice() {
+        [scheme, rounds, salt, hash] if *scheme == PASSWORD_HASH_SCHEME => {
+            match (rounds.parse::<u32>(), hex::decode(salt), hex::decode(hash)) {
+                (Ok(rounds), Ok(salt), Ok(hash)) => (rounds, salt, hash),
+                _ => return false,
+            }
+        }
+        _ => return false,
+    };
+    let derived = pbkdf2_sha256(password, &salt, rounds);
+    // compares every byte, so the time taken tells nothing about the hash
+    hash.len() == derived.len()
+        && hash
+            .iter()
+            .zip(derived.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Thumbnails are signed by folder, so a track and the sprites its cues point to, which may
+/// belong to other movies, share a signature
+pub fn signed_path(path: &str) -> &str {
+    if path.starts_with(crate::THUMBNAILS_ROUTE) {
+        path.rfind('/').map_or(path, |slash| &path[..=slash])
+    } else {
+        path
+    }
+}
+
+impl AuthConfig {
+    pub fn load(path: &str) -> Self {
+        info!("Loading auth config from {}", path);
+        let config = fs::read_to_string(path).expect(&format!("Error reading {}", path));
+        let config: Self =
+            serde_json::from_str(&config).expect(&format!("Invalid auth config {}", path));
+        for user in &config.users {
+            if !user.password_hash.starts_with(PASSWORD_HASH_SCHEME) {
+                panic!(
+                    "The password_hash of {} in {} is not a {} hash, see hash-password",
+                    user.username, path, PASSWORD_HASH_SCHEME
+                );
+            }
+        }
+        config
+    }
+
+    fn authenticate_bearer(&self, token: &str) -> Option<Authenticated> {
+        let token_sha256 = sha256_hex(token);
+        self.tokens
+            .iter()
+            .find(|t| t.token_sha256.eq_ignore_ascii_case(&token_sha256))
+            .map(|t| Authenticated {
+                name: t.name.clone(),
+                scopes: t.scopes.clone(),
+            })
+    }
+
+    fn authenticate_basic(&self, credentials: &str) -> Option<Authenticated> {
+        let credentials = base64::decode(credentials).ok()?;
+        let credentials = String::from_utf8(credentials).ok()?;
+        let mut credentials = credentials.splitn(2, ':');
+        let (username, password) = (credentials.next()?, credentials.next()?);
+        let user = self.users.iter().find(|u| u.username == username)?;
+        let credentials_sha256 = sha256_hex(&format!("{}:{}", username, password));
+        // not locked while deriving the hash, which takes a while
+        let already_verified = self
+            .verified_credentials
+            .lock()
+            .expect("Verified credentials lock poisoned")
+            .contains(&credentials_sha256);
+        if !already_verified {
+            if !verify_password(password, &user.password_hash) {
+                return None;
+            }
+            self.verified_credentials
+                .lock()
+                .expect("Verified credentials lock poisoned")
+                .insert(credentials_sha256);
+        }
+        Some(Authenticated {
+            name: user.username.clone(),
+            scopes: user.scopes.clone(),
+        })
+    }
+
+    fn authenticate(&self, authorization: &str) -> Option<Authenticated> {
+        let mut authorization = authorization.splitn(2, ' ');
+        match (authorization.next()?, authorization.next()?) {
+            ("Bearer", token) => self.authenticate_bearer(token.trim()),
+            ("Basic", credentials) => self.authenticate_basic(credentials.trim()),
+            _ => None,
+        }
+    }
+
+    fn mac(&self, path: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_secret.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(format!("{}:{}", path, expires).as_bytes());
+        mac
+    }
+
+    /// Query string granting access to path until the expires unix timestamp
+    pub fn sign(&self, path: &str, expires: i64) -> String {
+        let signature = hex::encode(self.mac(path, expires).finalize().into_bytes());
+        format!("expires={}&signature={}", expires, signature)
+    }
+
+    fn verify_signature(&self, path: &str, expires: i64, signature: &str) -> bool {
+        if expires < chrono::Local::now().timestamp() {
+            return false;
+        }
+        match hex::decode(signature) {
+            Ok(signature) => self.mac(path, expires).verify(&signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// A user or token authenticated by the Authorization header
+#[derive(Clone, Debug)]
+pub struct Authenticated {
+    pub name: String,
+    scopes: Vec<Scope>,
+}
+
+impl Authenticated {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), ApiError> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(ApiError::forbidden(format!(
+                "{} is missing the {:?} scope",
+                self.name, scope
+            )))
+        }
+    }
+}
+
+fn auth_config<'a>(request: &'a Request) -> &'a AuthConfig {
+    request
+        .guard::<State<AuthConfig>>()
+        .succeeded()
+        .expect("Auth config is not managed")
+        .inner()
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Authenticated {
+    type Error = ApiError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let authorization = match request.headers().get_one("Authorization") {
+            Some(authorization) => authorization,
+            None => {
+                return Outcome::Failure((
+                    Status::Unauthorized,
+                    ApiError::unauthorized("Missing Authorization header"),
+                ))
+            }
+        };
+        match auth_config(request).authenticate(authorization) {
+            Some(authenticated) => Outcome::Success(authenticated),
+            None => {
+                error!("Rejected credentials from {:?}", request.client_ip());
+                Outcome::Failure((
+                    Status::Unauthorized,
+                    ApiError::unauthorized("Invalid credentials"),
+                ))
+            }
+        }
+    }
+}
+
+/// Access to a route either by the Authorization header or, for <video> and <track> tags which
+/// can't set headers, by a signed expiring URL: ?expires=<unix timestamp>&signature=<hex HMAC>
+#[derive(Clone, Debug)]
+pub enum Access {
+    Authenticated(Authenticated),
+    /// the signature only covers the signed path, so it grants every scope on it
+    SignedUrl {
+        /// expires and signature, for the URLs of the response to be signed the same way
+        query: String,
+    },
+}
+
+impl Access {
+    pub fn require(&self, scope: Scope) -> Result<(), ApiError> {
+        match self {
+            Access::Authenticated(authenticated) => authenticated.require(scope),
+            Access::SignedUrl { .. } => Ok(()),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Access {
+    type Error = ApiError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let expires = request.get_query_value::<i64>("expires");
+        let signature = request.get_query_value::<String>("signature");
+        if let (Some(Ok(expires)), Some(Ok(signature))) = (expires, signature) {
+            let path = signed_path(request.uri().path());
+            return if auth_config(request).verify_signature(path, expires, &signature) {
+                Outcome::Success(Access::SignedUrl {
+                    query: format!("expires={}&signature={}", expires, signature),
+                })
+            } else {
+                Outcome::Failure((
+                    Status::Unauthorized,
+                    ApiError::unauthorized("Invalid or expired signature"),
+                ))
+            };
+        }
+        Authenticated::from_request(request).map(Access::Authenticated)
+    }
+}
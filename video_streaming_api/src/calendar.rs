@@ -0,0 +1,173 @@
+use crate::catalog::{catalog, CatalogMovie, MovieKind};
+use crate::errors::ApiError;
+use crate::frame_index::FrameIndex;
+use chrono::{Datelike, Local, NaiveDate, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Recording coverage of a day, as found in the catalog and the frame indexes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CalendarDay {
+    /// YYYY-MM-DD
+    date: String,
+    day_movie: bool,
+    /// None when a day movie has no frame index, the hours it covers are unknown
+    hours_recorded: Option<usize>,
+    /// None when a movie of the day has no frame index, the count would be wrong
+    total_frames: Option<usize>,
+    /// hours of the day without any frame, for today only the hours already over. None when a
+    /// day movie has no frame index.
+    gaps: Option<Vec<u32>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CalendarMonth {
+    month: String,
+    /// every day of the month up to today
+    days: Vec<CalendarDay>,
+}
+
+struct DayCoverage {
+    day_movie: bool,
+    /// None once a movie covering unknown hours is added
+    hours: Option<BTreeSet<u32>>,
+    frames: Option<usize>,
+}
+
+impl Default for DayCoverage {
+    fn default() -> Self {
+        Self {
+            day_movie: false,
+            hours: Some(BTreeSet::new()),
+            frames: Some(0),
+        }
+    }
+}
+
+impl DayCoverage {
+    /// Only the frames captured on the date count, a movie may go on past midnight
+    fn add(&mut self, date: NaiveDate, movie: &CatalogMovie, index: Option<FrameIndex>) {
+        match index {
+            Some(index) => {
+                let capture_times: Vec<_> = index
+                    .capture_times()
+                    .iter()
+                    .map(|millis| Local.timestamp_millis(*millis))
+                    .filter(|capture_time| capture_time.naive_local().date() == date)
+                    .collect();
+                if let Some(hours) = &mut self.hours {
+                    hours.extend(capture_times.iter().map(|capture_time| capture_time.hour()));
+                }
+                self.frames = self.frames.map(|frames| frames + capture_times.len());
+            }
+            None => {
+                // without index only hourly clips tell which hour they cover
+                match (&mut self.hours, movie.kind) {
+                    (Some(hours), MovieKind::Hourly) => {
+                        hours.insert(movie.date().hour());
+                    }
+                    _ => self.hours = None,
+                }
+                self.frames = None;
+            }
+        }
+    }
+
+    /// The gaps are only the hours already over
+    fn calendar_day(&self, date: NaiveDate, hours_over: u32) -> CalendarDay {
+        CalendarDay {
+            date: date.format("%Y-%m-%d").to_string(),
+            day_movie: self.day_movie,
+            hours_recorded: self.hours.as_ref().map(BTreeSet::len),
+            total_frames: self.frames,
+            gaps: self.hours.as_ref().map(|hours| {
+                (0..hours_over)
+                    .filter(|hour| !hours.contains(hour))
+                    .collect()
+            }),
+        }
+    }
+}
+
+/// Parses the YYYY-MM month of GET /calendar into its first day
+pub fn parse_month(month: &str) -> Result<NaiveDate, ApiError> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+        .map_err(|_| ApiError::bad_request(format!("Invalid month {}, expected YYYY-MM", month)))
+}
+
+pub fn calendar(first_day: NaiveDate) -> CalendarMonth {
+    let mut coverage: BTreeMap<NaiveDate, DayCoverage> = BTreeMap::new();
+    for movie in catalog() {
+        let date = movie.date().naive_local().date();
+        if date.year() != first_day.year() || date.month() != first_day.month() {
+            continue;
+        }
+        let day = coverage.entry(date).or_default();
+        if movie.kind == MovieKind::Daily {
+            day.day_movie = true;
+        }
+        day.add(date, &movie, FrameIndex::for_movie(&movie));
+    }
+
+    let now = Local::now().naive_local();
+    let mut days = vec![];
+    let mut date = first_day;
+    while date.month() == first_day.month() && date <= now.date() {
+        // the current hour is still being recorded
+        let hours_over = if date == now.date() { now.hour() } else { 24 };
+        let day = coverage.remove(&date).unwrap_or_default();
+        days.push(day.calendar_day(date, hours_over));
+        date = date.succ();
+    }
+    CalendarMonth {
+        month: first_day.format("%Y-%m").to_string(),
+        days,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::MovieId;
+
+    fn movie(kind: MovieKind, date: NaiveDate, hour: u32) -> CatalogMovie {
+        CatalogMovie {
+            id: MovieId(
+                Local
+                    .from_local_date(&date)
+                    .unwrap()
+                    .and_hms(hour, 0, 0)
+                    .timestamp(),
+            ),
+            kind,
+            path: String::new(),
+        }
+    }
+
+    #[test]
+    fn day_movie_without_index_has_unknown_coverage() {
+        let date = NaiveDate::from_ymd(2020, 6, 1);
+        let mut day = DayCoverage {
+            day_movie: true,
+            ..DayCoverage::default()
+        };
+        day.add(date, &movie(MovieKind::Hourly, date, 3), None);
+        day.add(date, &movie(MovieKind::Daily, date, 0), None);
+        let day = day.calendar_day(date, 24);
+        assert!(day.day_movie);
+        assert_eq!(day.hours_recorded, None);
+        assert_eq!(day.total_frames, None);
+        assert_eq!(day.gaps, None);
+    }
+
+    #[test]
+    fn hourly_clips_without_index_still_cover_their_hour() {
+        let date = NaiveDate::from_ymd(2020, 6, 1);
+        let mut day = DayCoverage::default();
+        day.add(date, &movie(MovieKind::Hourly, date, 3), None);
+        let day = day.calendar_day(date, 5);
+        assert_eq!(day.hours_recorded, Some(1));
+        assert_eq!(day.total_frames, None);
+        assert_eq!(day.gaps, Some(vec![0, 1, 2, 4]));
+    }
+}
@@ -0,0 +1,236 @@
+use crate::errors::ApiError;
+use crate::metadata::{Annotations, MovieMetadata};
+use crate::MOVIES_FOLDER_ROOT;
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use rocket::http::RawStr;
+use rocket::request::FromParam;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Movies are identified by the timestamp they are named after, which camera_api guarantees
+/// to be unique among day movies and hourly clips.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovieId(pub i64);
+
+/// Accepts a strict timestamp name, with or without the .mp4 extension ("1234" or "1234.mp4"),
+/// so nothing but catalog movies can be addressed.
+impl<'a> FromParam<'a> for MovieId {
+    type Error = ApiError;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+        let name = param.as_str();
+        let timestamp = name.strip_suffix(".mp4").unwrap_or(name);
+        if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ApiError::bad_request(format!("Invalid movie id: {}", name)));
+        }
+        timestamp
+            .parse()
+            .map(MovieId)
+            .map_err(|_| ApiError::bad_request(format!("Invalid movie id: {}", name)))
+    }
+}
+
+fn timestamp_name(filename: &str, extension: &str) -> Option<i64> {
+    let timestamp = filename.strip_suffix(extension)?;
+    if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    timestamp.parse().ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MovieKind {
+    /// a whole day stitched into a single movie
+    Daily,
+    /// a single hour of the current day, stored in the today folder
+    Hourly,
+}
+
+#[derive(Clone, Debug)]
+pub struct CatalogMovie {
+    pub id: MovieId,
+    pub kind: MovieKind,
+    /// path relative to MOVIES_FOLDER_ROOT, as used by /stream
+    pub path: String,
+}
+
+impl CatalogMovie {
+    pub fn full_path(&self) -> String {
+        format!("{}/{}", MOVIES_FOLDER_ROOT, self.path)
+    }
+
+    pub fn date(&self) -> DateTime<Local> {
+        Local.timestamp(self.id.0, 0)
+    }
+
+    /// Path relative to MOVIES_FOLDER_ROOT of a file stored next to the movie, such as
+    /// 1234.frames.json for 1234.mp4
+    pub fn sidecar_path(&self, suffix: &str) -> String {
+        self.path.replace(".mp4", suffix)
+    }
+}
+
+/// All the movies in MOVIES_FOLDER_ROOT: day movies named <timestamp>.mp4 and the hourly clips
+/// of the today folder, named <timestamp>/<timestamp>.mp4, along with those of the days whose
+/// stitch failed. Anything else is ignored.
+pub fn catalog() -> Vec<CatalogMovie> {
+    let dir = fs::read_dir(MOVIES_FOLDER_ROOT).expect("Error reading movies folder dir");
+    let mut movies = vec![];
+    for entry in dir {
+        let entry = entry.expect("Error reading entry from movies folder dir");
+        let metadata = entry.metadata().expect("No metadata for entry!");
+        let file_type = metadata.file_type();
+        let filename = entry.file_name().to_string_lossy().to_string();
+        if file_type.is_file() {
+            if let Some(timestamp) = timestamp_name(&filename, ".mp4") {
+                movies.push(CatalogMovie {
+                    id: MovieId(timestamp),
+                    kind: MovieKind::Daily,
+                    path: filename,
+                });
+            }
+        } else if file_type.is_dir() && timestamp_name(&filename, "").is_some() {
+            let entries = fs::read_dir(entry.path()).expect("Error reading today_movies folder");
+            for folder_entry in entries {
+                let folder_entry = folder_entry.expect("Error reading entry in today folder");
+                let file_type = folder_entry
+                    .file_type()
+                    .expect("Error reading folder entry in today folder");
+                let today_movie_filename = folder_entry.file_name().to_string_lossy().to_string();
+                if !file_type.is_file() {
+                    continue;
+                }
+                if let Some(timestamp) = timestamp_name(&today_movie_filename, ".mp4") {
+                    movies.push(CatalogMovie {
+                        id: MovieId(timestamp),
+                        kind: MovieKind::Hourly,
+                        path: format!("{}/{}", filename, today_movie_filename),
+                    });
+                }
+            }
+        }
+    }
+    movies
+}
+
+pub fn find_movie(id: MovieId) -> Result<CatalogMovie, ApiError> {
+    catalog()
+        .into_iter()
+        .find(|movie| movie.id == id)
+        .ok_or_else(|| ApiError::not_found(format!("No movie with id {}", id.0)))
+}
+
+/// Finds an hourly clip, checking it belongs to the given today folder
+pub fn find_today_movie(today_folder: MovieId, id: MovieId) -> Result<CatalogMovie, ApiError> {
+    let movie = find_movie(id)?;
+    let expected_path = format!("{}/{}.mp4", today_folder.0, id.0);
+    if movie.kind != MovieKind::Hourly || movie.path != expected_path {
+        return Err(ApiError::not_found(format!(
+            "No movie {} in today folder {}",
+            id.0, today_folder.0
+        )));
+    }
+    Ok(movie)
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct TodayMovie {
+    hour: u32,
+    filepath: String,
+    formatted_date: String,
+    #[serde(flatten)]
+    annotations: Annotations,
+}
+
+impl TodayMovie {
+    pub fn new(movie: &CatalogMovie, metadata: &MovieMetadata) -> Self {
+        let date = movie.date();
+        let formatted = format!("{}h", date.hour());
+        TodayMovie {
+            hour: date.hour(),
+            filepath: movie.path.clone(),
+            formatted_date: formatted,
+            annotations: Annotations::from(metadata),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct PastDayMovies {
+    formatted_date: String,
+    timestamp: u64,
+    filename: String,
+    #[serde(flatten)]
+    annotations: Annotations,
+}
+
+impl PastDayMovies {
+    pub fn new(movie: &CatalogMovie, metadata: &MovieMetadata) -> Self {
+        let date = movie.date();
+        let formatted = format!("{}-{}-{}", date.day(), date.month(), date.year());
+
+        Self {
+            formatted_date: formatted,
+            timestamp: date.timestamp() as u64,
+            filename: movie.path.clone(),
+            annotations: Annotations::from(metadata),
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct AvailableMovies {
+    past_day_movies: Vec<PastDayMovies>,
+    today_movies: Vec<TodayMovie>,
+    /// number of movies matching the query, across all pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    /// cursor of the next page, absent on the last one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl AvailableMovies {
+    pub fn push(&mut self, movie: &CatalogMovie, metadata: &MovieMetadata) {
+        match movie.kind {
+            MovieKind::Daily => self
+                .past_day_movies
+                .push(PastDayMovies::new(movie, metadata)),
+            MovieKind::Hourly => self.today_movies.push(TodayMovie::new(movie, metadata)),
+        }
+    }
+}
+
+/// Thumbnail sprites (<timestamp>.thumbs.jpg) and WebVTT tracks (<timestamp>.thumbs.vtt) are
+/// stored next to the movies they belong to. Sprites of hourly clips outlive the clips: the day
+/// movie track keeps pointing to them after stitching.
+#[derive(Clone, Debug)]
+pub struct ThumbnailFile {
+    pub filename: String,
+    pub is_track: bool,
+}
+
+impl<'a> FromParam<'a> for ThumbnailFile {
+    type Error = ApiError;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+        let name = param.as_str();
+        if timestamp_name(name, ".thumbs.vtt").is_some() {
+            Ok(ThumbnailFile {
+                filename: name.to_string(),
+                is_track: true,
+            })
+        } else if timestamp_name(name, ".thumbs.jpg").is_some() {
+            Ok(ThumbnailFile {
+                filename: name.to_string(),
+                is_track: false,
+            })
+        } else {
+            Err(ApiError::bad_request(format!(
+                "Invalid thumbnail file: {}",
+                name
+            )))
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, status, Responder, Response};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+/// Body of every error response of the API
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    error: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiError {
+    status: Status,
+    message: String,
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::BadRequest,
+            message: message.into(),
+        }
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Unauthorized,
+            message: message.into(),
+        }
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Forbidden,
+            message: message.into(),
+        }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::Conflict,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::NotFound,
+            message: message.into(),
+        }
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self {
+            status: Status::ServiceUnavailable,
+            message: message.into(),
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        status::Custom(
+            self.status,
+            Json(ErrorBody {
+                error: self.message,
+            }),
+        )
+        .respond_to(request)
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        Self {
+            status: Status::InternalServerError,
+            message: e.to_string(),
+        }
+    }
+}
+
+fn catch(status: Status) -> status::Custom<Json<ErrorBody>> {
+    status::Custom(
+        status,
+        Json(ErrorBody {
+            error: status.reason.to_string(),
+        }),
+    )
+}
+
+#[catch(400)]
+pub fn bad_request() -> status::Custom<Json<ErrorBody>> {
+    catch(Status::BadRequest)
+}
+
+/// Also asks browsers for HTTP Basic credentials
+#[catch(401)]
+pub fn unauthorized(request: &Request) -> response::Result<'static> {
+    Response::build_from(catch(Status::Unauthorized).respond_to(request)?)
+        .raw_header("WWW-Authenticate", "Basic realm=\"kitchen-timelapse\"")
+        .ok()
+}
+
+#[catch(403)]
+pub fn forbidden() -> status::Custom<Json<ErrorBody>> {
+    catch(Status::Forbidden)
+}
+
+#[catch(404)]
+pub fn not_found() -> status::Custom<Json<ErrorBody>> {
+    catch(Status::NotFound)
+}
+
+#[catch(409)]
+pub fn conflict() -> status::Custom<Json<ErrorBody>> {
+    catch(Status::Conflict)
+}
+
+#[catch(422)]
+pub fn unprocessable_entity() -> status::Custom<Json<ErrorBody>> {
+    catch(Status::UnprocessableEntity)
+}
+
+#[catch(500)]
+pub fn internal_error() -> status::Custom<Json<ErrorBody>> {
+    catch(Status::InternalServerError)
+}
@@ -0,0 +1,178 @@
+use crate::errors::ApiError;
+use log::{error, info};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Datagram socket camera_api sends its events to, one JSON object per datagram
+const EVENTS_SOCKET: &str = "/mnt/ram/recorder_events.sock";
+/// Events a slow client may lag behind before it starts missing them
+const SUBSCRIBER_BACKLOG: usize = 64;
+/// Idle clients get a comment this often so closed connections are noticed
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Rocket only writes a chunked body once it has read a whole chunk, so every message is
+/// padded to a multiple of this size to be sent as soon as it is received
+const SSE_CHUNK_SIZE: usize = 64;
+
+/// Forwards the recorder events to every /events client
+#[derive(Clone)]
+pub struct EventHub {
+    subscribers: Arc<Mutex<Vec<SyncSender<Vec<u8>>>>>,
+    /// each open stream holds a Rocket worker, some are left for the other requests
+    max_streams: usize,
+    /// streams not dropped yet
+    open_streams: Arc<AtomicUsize>,
+}
+
+impl EventHub {
+    /// Binds EVENTS_SOCKET and forwards what arrives on it from a background thread. Up to
+    /// half of the workers may serve /events, at least one.
+    pub fn listen(workers: usize) -> Self {
+        let hub = Self {
+            subscribers: Arc::default(),
+            max_streams: std::cmp::max(workers / 2, 1),
+            open_streams: Arc::default(),
+        };
+        // left over by a previous run
+        let _ = fs::remove_file(EVENTS_SOCKET);
+        let socket = match UnixDatagram::bind(EVENTS_SOCKET) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!(
+                    "Could not bind {}, no event will be sent: {}",
+                    EVENTS_SOCKET, e
+                );
+                return hub;
+            }
+        };
+        // the recorder runs as the same user or in the same group, see the systemd units, and no
+        // one else may send events
+        if let Err(e) = fs::set_permissions(EVENTS_SOCKET, fs::Permissions::from_mode(0o660)) {
+            error!("Could not set permissions of {}: {}", EVENTS_SOCKET, e);
+        }
+        info!("Listening for recorder events on {}", EVENTS_SOCKET);
+        let forwarding_hub = hub.clone();
+        std::thread::spawn(move || {
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                match socket.recv(&mut buffer) {
+                    Ok(len) => forwarding_hub.forward(&buffer[..len]),
+                    Err(e) => error!("Error receiving recorder event: {}", e),
+                }
+            }
+        });
+        hub
+    }
+
+    fn forward(&self, event: &[u8]) {
+        let event: serde_json::Value = match serde_json::from_slice(event) {
+            Ok(event) => event,
+            Err(e) => {
+                error!("Invalid recorder event: {}", e);
+                return;
+            }
+        };
+        let event_type = event["type"].as_str().unwrap_or("message");
+        let message = sse_message(&format!("event: {}\ndata: {}\n\n", event_type, event));
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("Event subscribers lock poisoned");
+        subscribers.retain(|subscriber| match subscriber.try_send(message.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                error!("Events client is lagging, dropping an event");
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    /// Fails when max_streams are open already
+    pub fn subscribe(&self) -> Result<EventStream, ApiError> {
+        let open_streams = self.open_streams.fetch_add(1, Ordering::SeqCst);
+        if open_streams >= self.max_streams {
+            self.open_streams.fetch_sub(1, Ordering::SeqCst);
+            return Err(ApiError::unavailable(format!(
+                "Already {} /events clients, try again later",
+                open_streams
+            )));
+        }
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        self.subscribers
+            .lock()
+            .expect("Event subscribers lock poisoned")
+            .push(sender);
+        Ok(EventStream {
+            receiver,
+            pending: vec![],
+            position: 0,
+            open_streams: self.open_streams.clone(),
+        })
+    }
+}
+
+/// Pads the message with a comment line, which clients ignore, up to a multiple of
+/// SSE_CHUNK_SIZE
+fn sse_message(message: &str) -> Vec<u8> {
+    let mut padding = SSE_CHUNK_SIZE - message.len() % SSE_CHUNK_SIZE;
+    if padding == SSE_CHUNK_SIZE {
+        return message.as_bytes().to_vec();
+    }
+    // the shortest comment line is ":\n"
+    if padding < 2 {
+        padding += SSE_CHUNK_SIZE;
+    }
+    let mut padded = format!(":{}\n", " ".repeat(padding - 2));
+    padded.push_str(message);
+    padded.into_bytes()
+}
+
+/// Body of a text/event-stream response, which ends when the client disconnects
+pub struct EventStream {
+    receiver: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    position: usize,
+    open_streams: Arc<AtomicUsize>,
+}
+
+/// Rocket drops the body once writing to the client fails, at the latest on the next keepalive
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.open_streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.pending.len() {
+            self.pending = match self.receiver.recv_timeout(KEEPALIVE_INTERVAL) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => sse_message(":keepalive\n\n"),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.position = 0;
+        }
+        let len = std::cmp::min(buf.len(), self.pending.len() - self.position);
+        buf[..len].copy_from_slice(&self.pending[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl<'r> Responder<'r> for EventStream {
+    fn respond_to(self, _request: &Request) -> response::Result<'r> {
+        Response::build()
+            .raw_header("Content-Type", "text/event-stream")
+            .raw_header("Cache-Control", "no-cache")
+            .chunked_body(self, SSE_CHUNK_SIZE as u64)
+            .ok()
+    }
+}
@@ -0,0 +1,42 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Where camera_api keeps the pictures of the segments it could not encode
+const FAILED_SEGMENTS_FOLDER: &str = "/mnt/skynet/failed_segments";
+
+/// failure.json written by camera_api next to the pictures of the segment
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SegmentFailure {
+    /// timestamp of the first picture, also the name of the folder
+    segment: i64,
+    exit_code: Option<i32>,
+    /// last lines ffmpeg printed
+    stderr_tail: String,
+    /// encoder profile of the recorder config, missing before profiles existed
+    #[serde(default)]
+    profile: Option<String>,
+    /// presets tried, in order, none for the encoders without presets
+    presets: Vec<String>,
+    failed_at: i64,
+}
+
+/// Segments waiting for a manual re-encode, oldest first
+pub fn failed_segments() -> Vec<SegmentFailure> {
+    let dir = match fs::read_dir(FAILED_SEGMENTS_FOLDER) {
+        Ok(dir) => dir,
+        Err(_) => return vec![],
+    };
+    let mut failures: Vec<SegmentFailure> = dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path().join("failure.json");
+            let failure = fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&failure)
+                .map_err(|e| error!("Invalid {:?}: {}", path, e))
+                .ok()
+        })
+        .collect();
+    failures.sort_by_key(|failure| failure.segment);
+    failures
+}
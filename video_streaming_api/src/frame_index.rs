@@ -0,0 +1,86 @@
+use crate::catalog::{catalog, CatalogMovie};
+use crate::MOVIES_FOLDER_ROOT;
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Written by camera_api next to each movie: 1234.mp4 -> 1234.frames.json
+/// Maps each frame of the movie to when it was captured.
+#[derive(Debug, Deserialize)]
+pub struct FrameIndex {
+    fps: u32,
+    /// capture time of each frame, as unix timestamps in milliseconds
+    capture_times: Vec<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureTime {
+    timestamp_millis: i64,
+    formatted_date: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MoviePosition {
+    /// path of the movie as used by /stream
+    filepath: String,
+    /// playback position in seconds
+    offset: f64,
+}
+
+impl FrameIndex {
+    pub fn for_movie(movie: &CatalogMovie) -> Option<Self> {
+        let index_path = format!(
+            "{}/{}",
+            MOVIES_FOLDER_ROOT,
+            movie.sidecar_path(".frames.json")
+        );
+        let index = fs::read_to_string(index_path).ok()?;
+        serde_json::from_str(&index).ok()
+    }
+
+    pub fn capture_times(&self) -> &[i64] {
+        &self.capture_times
+    }
+
+    pub fn capture_time_at(&self, offset: f64) -> Option<CaptureTime> {
+        if offset < 0. {
+            return None;
+        }
+        let frame = (offset * self.fps as f64) as usize;
+        let timestamp_millis = *self.capture_times.get(frame)?;
+        Some(CaptureTime {
+            timestamp_millis,
+            formatted_date: Local
+                .timestamp_millis(timestamp_millis)
+                .format("%d-%m-%Y %X")
+                .to_string(),
+        })
+    }
+
+    /// Playback position of the last frame captured at or before timestamp_millis, if the movie
+    /// covers that time
+    pub fn offset_of(&self, timestamp_millis: i64) -> Option<f64> {
+        let first = *self.capture_times.first()?;
+        let last = *self.capture_times.last()?;
+        if timestamp_millis < first || timestamp_millis > last {
+            return None;
+        }
+        let frame = match self.capture_times.binary_search(&timestamp_millis) {
+            Ok(frame) => frame,
+            Err(next_frame) => next_frame - 1,
+        };
+        Some(frame as f64 / self.fps as f64)
+    }
+}
+
+/// Finds which movie contains the frame captured at timestamp_millis and where
+pub fn locate(timestamp_millis: i64) -> Option<(MoviePosition, CatalogMovie)> {
+    catalog().into_iter().find_map(|movie| {
+        let offset = FrameIndex::for_movie(&movie)?.offset_of(timestamp_millis)?;
+        let position = MoviePosition {
+            filepath: movie.path.clone(),
+            offset,
+        };
+        Some((position, movie))
+    })
+}
@@ -0,0 +1,186 @@
+use crate::MOVIES_FOLDER_ROOT;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Written every 10 seconds by camera_api, see its status module
+const RECORDER_STATUS_FILE: &str = "/mnt/ram/recorder_status.json";
+/// Where the NAS holding the movies is mounted
+const STORAGE_MOUNT: &str = "/mnt/skynet";
+const MAX_STATUS_AGE_MILLIS: i64 = 60 * 1000;
+/// the camera is restarted within seconds, minutes without frames means capture is broken
+const MAX_FRAME_AGE_MILLIS: i64 = 5 * 60 * 1000;
+/// an hour is encoded while the next one is recorded, so it has to be done within the hour
+const MAX_ENCODING_MILLIS: i64 = 50 * 60 * 1000;
+
+#[derive(Clone, Debug, Deserialize)]
+struct RecorderStatus {
+    updated_at: i64,
+    last_frame_at: Option<i64>,
+    segment_started_at: Option<i64>,
+    encoding_started_at: Option<i64>,
+    #[serde(default)]
+    encode_progress: Option<EncodeProgress>,
+}
+
+/// Progress of the running hourly encode, see camera_api EncodeProgress
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncodeProgress {
+    movie: String,
+    frame: u64,
+    total_frames: u64,
+    fps: f64,
+    out_time_millis: i64,
+    speed: Option<f64>,
+    eta_secs: Option<u64>,
+    segment_ends_at: i64,
+    finishes_before_segment_end: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Check {
+    pub ok: bool,
+    detail: String,
+}
+
+impl Check {
+    fn ok(detail: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn failed(detail: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Health {
+    pub healthy: bool,
+    recorder: Check,
+    last_frame: Check,
+    picture_thread: Check,
+    encoder: Check,
+    storage: Check,
+}
+
+fn age_secs(now: i64, timestamp_millis: i64) -> i64 {
+    (now - timestamp_millis) / 1000
+}
+
+/// The movies folder is on the NAS, which must be mounted and readable
+pub fn storage_check() -> Check {
+    let mounts = match fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => mounts,
+        Err(e) => return Check::failed(format!("Could not read /proc/mounts: {}", e)),
+    };
+    let mounted = mounts
+        .lines()
+        .any(|mount| mount.split_whitespace().nth(1) == Some(STORAGE_MOUNT));
+    if !mounted {
+        return Check::failed(format!("{} is not mounted", STORAGE_MOUNT));
+    }
+    match fs::read_dir(MOVIES_FOLDER_ROOT) {
+        Ok(_) => Check::ok(format!("{} is mounted", STORAGE_MOUNT)),
+        Err(e) => Check::failed(format!("Could not read {}: {}", MOVIES_FOLDER_ROOT, e)),
+    }
+}
+
+fn recorder_status() -> Result<RecorderStatus, String> {
+    fs::read_to_string(RECORDER_STATUS_FILE)
+        .map_err(|e| format!("Could not read {}: {}", RECORDER_STATUS_FILE, e))
+        .and_then(|status| {
+            serde_json::from_str::<RecorderStatus>(&status)
+                .map_err(|e| format!("Invalid {}: {}", RECORDER_STATUS_FILE, e))
+        })
+}
+
+/// None when the recorder is not encoding, or its status is unknown
+pub fn encode_progress() -> Option<EncodeProgress> {
+    let status = recorder_status().ok()?;
+    // a dead recorder leaves its last progress behind
+    if Local::now().timestamp_millis() - status.updated_at > MAX_STATUS_AGE_MILLIS {
+        return None;
+    }
+    status.encode_progress
+}
+
+pub fn health() -> Health {
+    let now = Local::now().timestamp_millis();
+    let storage = storage_check();
+    let status = match recorder_status() {
+        Ok(status) => status,
+        Err(e) => {
+            let unknown = || Check::failed("Recorder status unknown");
+            return Health {
+                healthy: false,
+                recorder: Check::failed(e),
+                last_frame: unknown(),
+                picture_thread: unknown(),
+                encoder: unknown(),
+                storage,
+            };
+        }
+    };
+
+    let recorder = if now - status.updated_at > MAX_STATUS_AGE_MILLIS {
+        Check::failed(format!(
+            "No status update for {}s",
+            age_secs(now, status.updated_at)
+        ))
+    } else {
+        Check::ok("Running")
+    };
+    let last_frame = match status.last_frame_at {
+        Some(last_frame_at) if now - last_frame_at > MAX_FRAME_AGE_MILLIS => Check::failed(
+            format!("Last frame captured {}s ago", age_secs(now, last_frame_at)),
+        ),
+        Some(last_frame_at) => Check::ok(format!(
+            "Last frame captured {}s ago",
+            age_secs(now, last_frame_at)
+        )),
+        None => Check::failed("No frame captured yet"),
+    };
+    let picture_thread = match status.segment_started_at {
+        Some(started_at) => Check::ok(format!(
+            "Taking pictures since {}s",
+            age_secs(now, started_at)
+        )),
+        None => Check::failed("Not taking pictures"),
+    };
+    let encoder = match status.encoding_started_at {
+        Some(started_at) if now - started_at > MAX_ENCODING_MILLIS => {
+            Check::failed(format!("Encoding stuck for {}s", age_secs(now, started_at)))
+        }
+        Some(started_at) => match &status.encode_progress {
+            Some(progress) => Check::ok(format!(
+                "Encoding for {}s, frame {} of {}{}",
+                age_secs(now, started_at),
+                progress.frame,
+                progress.total_frames,
+                match progress.finishes_before_segment_end {
+                    Some(false) => ", will not finish before the segment ends",
+                    _ => "",
+                }
+            )),
+            None => Check::ok(format!("Encoding for {}s", age_secs(now, started_at))),
+        },
+        None => Check::ok("Idle"),
+    };
+    let healthy = [&recorder, &last_frame, &picture_thread, &encoder, &storage]
+        .iter()
+        .all(|check| check.ok);
+    Health {
+        healthy,
+        recorder,
+        last_frame,
+        picture_thread,
+        encoder,
+        storage,
+    }
+}
@@ -0,0 +1,117 @@
+use crate::catalog::{catalog, AvailableMovies, CatalogMovie, MovieKind};
+use crate::errors::ApiError;
+use crate::metadata::MovieMetadata;
+
+/// Kinds of movies /movies can be restricted to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KindFilter {
+    Hourly,
+    Daily,
+}
+
+impl KindFilter {
+    pub fn parse(kind: &str) -> Result<Self, ApiError> {
+        match kind {
+            "hourly" => Ok(KindFilter::Hourly),
+            "daily" => Ok(KindFilter::Daily),
+            // the recorder makes no weekly movies
+            _ => Err(ApiError::bad_request(format!(
+                "Invalid kind {}, expected hourly or daily",
+                kind
+            ))),
+        }
+    }
+
+    fn matches(self, kind: MovieKind) -> bool {
+        match self {
+            KindFilter::Hourly => kind == MovieKind::Hourly,
+            KindFilter::Daily => kind == MovieKind::Daily,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+impl Order {
+    pub fn parse(order: &str) -> Result<Self, ApiError> {
+        match order {
+            "asc" => Ok(Order::Ascending),
+            "desc" => Ok(Order::Descending),
+            _ => Err(ApiError::bad_request(format!(
+                "Invalid order {}, expected asc or desc",
+                order
+            ))),
+        }
+    }
+}
+
+/// Query of GET /movies, without any parameter every movie is listed
+#[derive(Clone, Debug)]
+pub struct ListQuery {
+    /// unix timestamps (in seconds) bounding when the movies started recording
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub limit: Option<usize>,
+    /// next_cursor of the previous page
+    pub cursor: Option<i64>,
+    pub order: Order,
+    pub kind: Option<KindFilter>,
+}
+
+pub fn parse_cursor(cursor: &str) -> Result<i64, ApiError> {
+    cursor
+        .parse()
+        .map_err(|_| ApiError::bad_request(format!("Invalid cursor {}", cursor)))
+}
+
+/// One page of the movies matching the query, sorted by timestamp. The filter decides which
+/// movies may be listed at all, for instance the ones the user is allowed to watch.
+pub fn list_movies<F>(query: &ListQuery, filter: F) -> AvailableMovies
+where
+    F: Fn(&CatalogMovie) -> bool,
+{
+    let mut movies: Vec<CatalogMovie> = catalog()
+        .into_iter()
+        .filter(|movie| {
+            filter(movie)
+                && query.kind.map_or(true, |kind| kind.matches(movie.kind))
+                && query.from.map_or(true, |from| movie.id.0 >= from)
+                && query.to.map_or(true, |to| movie.id.0 <= to)
+        })
+        .collect();
+    movies.sort_by_key(|movie| movie.id.0);
+    if query.order == Order::Descending {
+        movies.reverse();
+    }
+    let total = movies.len();
+
+    // the cursor is the timestamp of the last movie of the previous page
+    let start = match query.cursor {
+        Some(cursor) => movies
+            .iter()
+            .position(|movie| match query.order {
+                Order::Ascending => movie.id.0 > cursor,
+                Order::Descending => movie.id.0 < cursor,
+            })
+            .unwrap_or(movies.len()),
+        None => 0,
+    };
+    let end = match query.limit {
+        Some(limit) => std::cmp::min(start.saturating_add(limit), movies.len()),
+        None => movies.len(),
+    };
+
+    let mut page = AvailableMovies::default();
+    for movie in &movies[start..end] {
+        page.push(movie, &MovieMetadata::read(movie));
+    }
+    page.total = Some(total);
+    if end < movies.len() && end > start {
+        page.next_cursor = Some(movies[end - 1].id.0.to_string());
+    }
+    page
+}
@@ -2,131 +2,487 @@
 #![feature(proc_macro_hygiene)]
 #[macro_use]
 extern crate rocket;
-use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use auth::{hash_password, movie_scope, signed_path, Access, AuthConfig, Authenticated, Scope};
+use calendar::CalendarMonth;
+use catalog::{
+    find_movie, find_today_movie, AvailableMovies, CatalogMovie, MovieId, ThumbnailFile,
+};
+use errors::ApiError;
+use events::{EventHub, EventStream};
+use failures::SegmentFailure;
 use flexi_logger::{Cleanup, Criterion, Naming};
+use frame_index::{CaptureTime, FrameIndex, MoviePosition};
+use health::{Check, EncodeProgress, Health};
+use listing::{list_movies, parse_cursor, KindFilter, ListQuery, Order};
+use metadata::{MetadataUpdate, MovieMetadata, NewTags};
+use metrics::RequestMetrics;
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Status};
+use rocket::response::content::Content;
+use rocket::response::status::{self, NoContent};
+use rocket::State;
 use rocket_contrib::json::Json;
 use rocket_cors::{AllowedHeaders, AllowedOrigins};
 use rocket_seek_stream::SeekStream;
+use search::SearchQuery;
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io::BufRead;
+
+mod auth;
+mod calendar;
+mod catalog;
+mod errors;
+mod events;
+mod failures;
+mod frame_index;
+mod health;
+mod listing;
+mod metadata;
+mod metrics;
+mod search;
+mod systemd;
 
 const MOVIES_FOLDER_ROOT: &str = "/mnt/skynet/movies";
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
-pub struct TodayMovie {
-    hour: u32,
-    filepath: String,
-    formatted_date: String,
-}
-
-impl TodayMovie {
-    pub fn new(folder_name: &str, filename: &str) -> Self {
-        let date = filename_to_date(filename);
-        let formatted = format!("{}h", date.hour());
-        TodayMovie {
-            hour: date.hour(),
-            filepath: format!("{}/{}", folder_name, filename),
-            formatted_date: formatted,
-        }
-    }
+/// Streamed files are addressed by their path, which the signature of signed URLs covers
+const STREAM_ROUTE: &str = "/stream";
+/// Thumbnails are signed by folder, see auth::signed_path
+const THUMBNAILS_ROUTE: &str = "/thumbnails";
+const EVENTS_ROUTE: &str = "/events";
+const DEFAULT_SIGNED_URL_TTL_SECS: i64 = 6 * 60 * 60;
+const MAX_SIGNED_URL_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+#[get("/stream/<movie>")]
+fn stream<'a>(
+    access: Access,
+    movie: Result<MovieId, ApiError>,
+) -> Result<SeekStream<'a>, ApiError> {
+    let movie = find_movie(movie?)?;
+    access.require(movie_scope(&movie))?;
+    Ok(SeekStream::from_path(movie.full_path())?)
 }
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
-pub struct PastDayMovies {
-    formatted_date: String,
-    timestamp: u64,
-    filename: String,
+#[get("/stream/<today_folder>/<today_filename>")]
+fn stream_today<'a>(
+    access: Access,
+    today_folder: Result<MovieId, ApiError>,
+    today_filename: Result<MovieId, ApiError>,
+) -> Result<SeekStream<'a>, ApiError> {
+    let movie = find_today_movie(today_folder?, today_filename?)?;
+    access.require(movie_scope(&movie))?;
+    Ok(SeekStream::from_path(movie.full_path())?)
 }
 
-pub fn filename_to_date(filename: &str) -> DateTime<Local> {
-    assert!(
-        filename.ends_with(".mp4"),
-        "File does not end with .mp4: {}",
-        filename
-    );
-    let timestamp: u64 = filename
-        .replace(".mp4", "")
-        .parse::<u64>()
-        .expect(&format!("File in dir was not timestamp: {}", filename));
-    chrono::Local.timestamp(timestamp as i64, 0)
-}
-impl PastDayMovies {
-    pub fn new(filename: &str) -> Self {
-        let date = filename_to_date(filename);
-        let formatted = format!("{}-{}-{}", date.day(), date.month(), date.year());
-
-        Self {
-            formatted_date: formatted,
-            timestamp: date.timestamp() as u64,
-            filename: filename.to_string(),
-        }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedUrl {
+    url: String,
+    /// thumbnails track of the movie, its sprites are signed along with it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thumbnails_url: Option<String>,
+    expires: i64,
+}
+
+/// When a signed URL valid for ttl seconds expires
+fn signed_url_expiry(ttl: Option<i64>) -> Result<i64, ApiError> {
+    let ttl = ttl.unwrap_or(DEFAULT_SIGNED_URL_TTL_SECS);
+    if ttl <= 0 || ttl > MAX_SIGNED_URL_TTL_SECS {
+        return Err(ApiError::bad_request(format!(
+            "ttl must be between 1 and {} seconds",
+            MAX_SIGNED_URL_TTL_SECS
+        )));
     }
+    Ok(chrono::Local::now().timestamp() + ttl)
 }
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
-pub struct AvailableMovies {
-    past_day_movies: Vec<PastDayMovies>,
-    today_movies: Vec<TodayMovie>,
-}
-
-pub fn dir_curr_files() -> AvailableMovies {
-    let dir = fs::read_dir(MOVIES_FOLDER_ROOT).expect("Error reading movies folder dir");
-    let mut available_movies = AvailableMovies::default();
-    for entry in dir {
-        let entry = entry.expect("Error reading entry from movies folder dir");
-        let metadata = entry.metadata().expect("No metadata for entry!");
-        let file_type = metadata.file_type();
-        // Files in folder it should end with .mp4
-        let filename = entry.file_name().to_string_lossy().to_string();
-        let is_mp4 = filename.ends_with(".mp4");
-        if file_type.is_file() && is_mp4 {
-            let movie = PastDayMovies::new(&filename);
-            available_movies.past_day_movies.push(movie);
-        }
-        if file_type.is_dir() {
-            let entries = fs::read_dir(entry.path()).expect("Error reading today_movies folder");
-            for folder_entry in entries {
-                let folder_entry = folder_entry.expect("Error reading entry in today folder");
-                let file_type = folder_entry
-                    .file_type()
-                    .expect("Error reading folder entry in today folder");
-                let is_mp4 = folder_entry.file_name().to_string_lossy().ends_with(".mp4");
-                let today_movie_filename = folder_entry.file_name().to_string_lossy().to_string();
-                if file_type.is_file() && is_mp4 {
-                    let today_movie = TodayMovie::new(&filename, &today_movie_filename);
-                    available_movies.today_movies.push(today_movie);
-                }
-            }
-        }
+fn sign_movie(
+    user: Authenticated,
+    auth_config: State<AuthConfig>,
+    movie: CatalogMovie,
+    ttl: Option<i64>,
+) -> Result<Json<SignedUrl>, ApiError> {
+    user.require(movie_scope(&movie))?;
+    let expires = signed_url_expiry(ttl)?;
+    let path = format!("{}/{}", STREAM_ROUTE, movie.path);
+    let query = auth_config.sign(&path, expires);
+    let track_path = format!(
+        "{}/{}",
+        THUMBNAILS_ROUTE,
+        movie.path.replace(".mp4", ".thumbs.vtt")
+    );
+    let track_query = auth_config.sign(signed_path(&track_path), expires);
+    Ok(Json(SignedUrl {
+        url: format!("{}?{}", path, query),
+        thumbnails_url: Some(format!("{}?{}", track_path, track_query)),
+        expires,
+    }))
+}
+
+/// Expiring URLs of /stream and /thumbnails, for <video> and <track> tags which can't send the
+/// Authorization header
+#[get("/sign/<movie>?<ttl>")]
+fn sign(
+    user: Authenticated,
+    auth_config: State<AuthConfig>,
+    movie: Result<MovieId, ApiError>,
+    ttl: Option<i64>,
+) -> Result<Json<SignedUrl>, ApiError> {
+    sign_movie(user, auth_config, find_movie(movie?)?, ttl)
+}
+
+#[get("/sign/<today_folder>/<today_filename>?<ttl>")]
+fn sign_today(
+    user: Authenticated,
+    auth_config: State<AuthConfig>,
+    today_folder: Result<MovieId, ApiError>,
+    today_filename: Result<MovieId, ApiError>,
+    ttl: Option<i64>,
+) -> Result<Json<SignedUrl>, ApiError> {
+    let movie = find_today_movie(today_folder?, today_filename?)?;
+    sign_movie(user, auth_config, movie, ttl)
+}
+
+/// Tracks fetched with a signed URL point to their sprites with the same signature, as the
+/// player fetches them without headers too
+fn thumbnail_file(
+    access: &Access,
+    path: String,
+    file: ThumbnailFile,
+) -> Result<Content<Vec<u8>>, ApiError> {
+    let content = fs::read(path)
+        .map_err(|_| ApiError::not_found(format!("No thumbnail file {}", file.filename)))?;
+    if !file.is_track {
+        return Ok(Content(ContentType::JPEG, content));
     }
-    available_movies
+    let content = match access {
+        Access::SignedUrl { query } => String::from_utf8_lossy(&content)
+            .replace(".thumbs.jpg#", &format!(".thumbs.jpg?{}#", query))
+            .into_bytes(),
+        Access::Authenticated(_) => content,
+    };
+    Ok(Content(ContentType::new("text", "vtt"), content))
 }
 
-#[get("/stream/<movie_path>")]
-fn stream<'a>(movie_path: String) -> std::io::Result<SeekStream<'a>> {
-    SeekStream::from_path(format!("{}/{}", MOVIES_FOLDER_ROOT, movie_path))
+#[get("/thumbnails/<file>")]
+fn thumbnails(
+    access: Access,
+    file: Result<ThumbnailFile, ApiError>,
+) -> Result<Content<Vec<u8>>, ApiError> {
+    access.require(Scope::Archive)?;
+    let file = file?;
+    thumbnail_file(
+        &access,
+        format!("{}/{}", MOVIES_FOLDER_ROOT, file.filename),
+        file,
+    )
 }
 
-#[get("/stream/<today_folder>/<today_filename>")]
-fn stream_today<'a>(
-    today_folder: String,
-    today_filename: String,
-) -> std::io::Result<SeekStream<'a>> {
-    SeekStream::from_path(format!(
-        "{}/{}/{}",
-        MOVIES_FOLDER_ROOT, today_folder, today_filename
+#[get("/thumbnails/<today_folder>/<file>")]
+fn thumbnails_today(
+    access: Access,
+    today_folder: Result<MovieId, ApiError>,
+    file: Result<ThumbnailFile, ApiError>,
+) -> Result<Content<Vec<u8>>, ApiError> {
+    access.require(Scope::Live)?;
+    let (today_folder, file) = (today_folder?, file?);
+    thumbnail_file(
+        &access,
+        format!(
+            "{}/{}/{}",
+            MOVIES_FOLDER_ROOT, today_folder.0, file.filename
+        ),
+        file,
+    )
+}
+
+fn movie_capture_time(
+    user: Authenticated,
+    movie: CatalogMovie,
+    offset: f64,
+) -> Result<Json<CaptureTime>, ApiError> {
+    user.require(movie_scope(&movie))?;
+    FrameIndex::for_movie(&movie)
+        .ok_or_else(|| ApiError::not_found(format!("Movie {} has no frame index", movie.id.0)))?
+        .capture_time_at(offset)
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::not_found(format!("Offset {} out of movie {}", offset, movie.id.0))
+        })
+}
+
+#[get("/capture_time/<movie>?<offset>")]
+fn capture_time(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+    offset: f64,
+) -> Result<Json<CaptureTime>, ApiError> {
+    movie_capture_time(user, find_movie(movie?)?, offset)
+}
+
+#[get("/capture_time/<today_folder>/<today_filename>?<offset>")]
+fn capture_time_today(
+    user: Authenticated,
+    today_folder: Result<MovieId, ApiError>,
+    today_filename: Result<MovieId, ApiError>,
+    offset: f64,
+) -> Result<Json<CaptureTime>, ApiError> {
+    let movie = find_today_movie(today_folder?, today_filename?)?;
+    movie_capture_time(user, movie, offset)
+}
+
+/// Which movie, and where in it, shows the given unix timestamp (in seconds)
+#[get("/locate?<timestamp>")]
+fn locate(user: Authenticated, timestamp: i64) -> Result<Json<MoviePosition>, ApiError> {
+    let timestamp_millis = timestamp
+        .checked_mul(1000)
+        .ok_or_else(|| ApiError::bad_request(format!("Invalid timestamp {}", timestamp)))?;
+    let (position, movie) = frame_index::locate(timestamp_millis)
+        .ok_or_else(|| ApiError::not_found(format!("No movie shows timestamp {}", timestamp)))?;
+    user.require(movie_scope(&movie))?;
+    Ok(Json(position))
+}
+
+#[get("/movies?<from>&<to>&<limit>&<cursor>&<order>&<kind>")]
+fn movies(
+    user: Authenticated,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<usize>,
+    cursor: Option<String>,
+    order: Option<String>,
+    kind: Option<String>,
+) -> Result<Json<AvailableMovies>, ApiError> {
+    let query = ListQuery {
+        from,
+        to,
+        limit,
+        cursor: cursor.as_deref().map(parse_cursor).transpose()?,
+        order: order
+            .as_deref()
+            .map(Order::parse)
+            .transpose()?
+            .unwrap_or(Order::Ascending),
+        kind: kind.as_deref().map(KindFilter::parse).transpose()?,
+    };
+    Ok(Json(list_movies(&query, |movie| {
+        user.has_scope(movie_scope(movie))
+    })))
+}
+
+#[get("/calendar?<month>")]
+fn calendar(user: Authenticated, month: String) -> Result<Json<CalendarMonth>, ApiError> {
+    user.require(Scope::Archive)?;
+    let first_day = calendar::parse_month(&month)?;
+    Ok(Json(calendar::calendar(first_day)))
+}
+
+/// Segments the recorder could not encode, their pictures are kept for a manual re-encode
+#[get("/failures")]
+fn failures(user: Authenticated) -> Result<Json<Vec<SegmentFailure>>, ApiError> {
+    user.require(Scope::Admin)?;
+    Ok(Json(failures::failed_segments()))
+}
+
+/// Server-Sent Events stream of what the recorder is doing, see camera_api RecorderEvent.
+/// Browsers' EventSource can't send the Authorization header, it uses a URL from /sign/events.
+/// The events cover both the live recording and the archive, so it takes the admin scope.
+#[get("/events")]
+fn events(access: Access, hub: State<EventHub>) -> Result<EventStream, ApiError> {
+    access.require(Scope::Admin)?;
+    hub.subscribe()
+}
+
+/// Expiring URL of /events
+#[get("/sign/events?<ttl>")]
+fn sign_events(
+    user: Authenticated,
+    auth_config: State<AuthConfig>,
+    ttl: Option<i64>,
+) -> Result<Json<SignedUrl>, ApiError> {
+    user.require(Scope::Admin)?;
+    let expires = signed_url_expiry(ttl)?;
+    Ok(Json(SignedUrl {
+        url: format!(
+            "{}?{}",
+            EVENTS_ROUTE,
+            auth_config.sign(EVENTS_ROUTE, expires)
+        ),
+        thumbnails_url: None,
+        expires,
+    }))
+}
+
+/// Progress of the hourly encode the recorder is running, with a prediction of whether it ends
+/// before the segment being recorded. Also sent as encode_progress events on /events.
+#[get("/encoding")]
+fn encoding(_user: Authenticated) -> Result<Json<EncodeProgress>, ApiError> {
+    health::encode_progress()
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("No encode running"))
+}
+
+/// Liveness of the recorder, 503 if any check fails so a watchdog can restart the services.
+/// Not authenticated, uptime monitors rarely support credentials and it only shows timings.
+#[get("/health")]
+fn health() -> status::Custom<Json<Health>> {
+    let health = health::health();
+    let status = if health.healthy {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    status::Custom(status, Json(health))
+}
+
+/// Whether the API can serve movies, 503 if the storage is unavailable
+#[get("/ready")]
+fn ready() -> status::Custom<Json<Check>> {
+    let storage = health::storage_check();
+    let status = if storage.ok {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    status::Custom(status, Json(storage))
+}
+
+/// Prometheus metrics, scraped with a bearer token or basic credentials of the admin scope
+#[get("/metrics")]
+fn metrics(user: Authenticated) -> Result<Content<String>, ApiError> {
+    user.require(Scope::Admin)?;
+    Ok(Content(
+        ContentType::with_params("text", "plain", ("version", "0.0.4")),
+        metrics::render(),
     ))
 }
 
-#[get("/movies")]
-fn movies() -> Json<AvailableMovies> {
-    Json(dir_curr_files())
+#[get("/search?<q>&<tag>&<from>&<to>")]
+fn search(
+    user: Authenticated,
+    q: Option<String>,
+    tag: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Json<AvailableMovies> {
+    let query = SearchQuery {
+        text: q,
+        tag,
+        from,
+        to,
+    };
+    Json(search::search(&query, |movie| {
+        user.has_scope(movie_scope(movie))
+    }))
+}
+
+/// Tagging is open to everyone allowed to watch the movie
+#[post("/movies/<movie>/tags", format = "json", data = "<new_tags>")]
+fn add_tags(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+    new_tags: Json<NewTags>,
+) -> Result<Json<MovieMetadata>, ApiError> {
+    let movie = find_movie(movie?)?;
+    user.require(movie_scope(&movie))?;
+    let mut metadata = MovieMetadata::read(&movie);
+    metadata.add_tags(new_tags.into_inner().tags);
+    metadata.write(&movie)?;
+    Ok(Json(metadata))
+}
+
+#[delete("/movies/<movie>/tags/<tag>")]
+fn remove_tag(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+    tag: String,
+) -> Result<Json<MovieMetadata>, ApiError> {
+    let movie = find_movie(movie?)?;
+    user.require(movie_scope(&movie))?;
+    let mut metadata = MovieMetadata::read(&movie);
+    metadata.remove_tag(&tag);
+    metadata.write(&movie)?;
+    Ok(Json(metadata))
+}
+
+fn admin_movie(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+) -> Result<CatalogMovie, ApiError> {
+    user.require(Scope::Admin)?;
+    find_movie(movie?)
+}
+
+#[delete("/movies/<movie>")]
+fn delete_movie(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+) -> Result<NoContent, ApiError> {
+    let movie = admin_movie(user, movie)?;
+    metadata::delete_movie(&movie)?;
+    Ok(NoContent)
+}
+
+fn set_pinned(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+    pinned: bool,
+) -> Result<Json<MovieMetadata>, ApiError> {
+    let movie = admin_movie(user, movie)?;
+    let mut metadata = MovieMetadata::read(&movie);
+    metadata.pinned = pinned;
+    metadata.write(&movie)?;
+    Ok(Json(metadata))
+}
+
+#[post("/movies/<movie>/pin")]
+fn pin_movie(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+) -> Result<Json<MovieMetadata>, ApiError> {
+    set_pinned(user, movie, true)
+}
+
+#[delete("/movies/<movie>/pin")]
+fn unpin_movie(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+) -> Result<Json<MovieMetadata>, ApiError> {
+    set_pinned(user, movie, false)
+}
+
+#[patch("/movies/<movie>", format = "json", data = "<update>")]
+fn update_movie(
+    user: Authenticated,
+    movie: Result<MovieId, ApiError>,
+    update: Json<MetadataUpdate>,
+) -> Result<Json<MovieMetadata>, ApiError> {
+    let movie = admin_movie(user, movie)?;
+    let mut metadata = MovieMetadata::read(&movie);
+    metadata.apply(update.into_inner());
+    metadata.write(&movie)?;
+    Ok(Json(metadata))
+}
+
+/// Prints the password_hash to put in auth.json for the password read from stdin
+fn print_password_hash() {
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .expect("Error reading the password from stdin");
+    println!(
+        "{}",
+        hash_password(password.trim_end_matches(&['\r', '\n'][..]))
+    );
 }
 
 fn main() {
-    let allowed_origins = AllowedOrigins::All;
     use rocket::http::Method;
 
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        return print_password_hash();
+    }
+
     use flexi_logger::colored_opt_format;
     use log::info;
     flexi_logger::Logger::with_str("info")
@@ -143,18 +499,74 @@ fn main() {
     log_panics::init();
     info!("Starting up...");
 
+    let rocket = rocket::ignite();
+    let workers = rocket.config().workers as usize;
+    let auth_config = AuthConfig::load(rocket.config().get_str("auth_file").unwrap_or("auth.json"));
+
     let cors = rocket_cors::CorsOptions {
-        allowed_origins,
-        allowed_methods: vec![Method::Get].into_iter().map(From::from).collect(),
-        allowed_headers: AllowedHeaders::All,
+        allowed_origins: AllowedOrigins::some_exact(&auth_config.allowed_origins),
+        allowed_methods: vec![Method::Get, Method::Post, Method::Patch, Method::Delete]
+            .into_iter()
+            .map(From::from)
+            .collect(),
+        allowed_headers: AllowedHeaders::some(&[
+            "Authorization",
+            "Accept",
+            "Content-Type",
+            "Range",
+        ]),
         allow_credentials: true,
         ..Default::default()
     }
     .to_cors()
     .unwrap();
 
-    rocket::ignite()
+    rocket
         .attach(cors)
-        .mount("/", routes![stream, movies, stream_today])
+        .attach(RequestMetrics)
+        .attach(AdHoc::on_launch("systemd notification", |_| {
+            systemd::notify("READY=1")
+        }))
+        .manage(auth_config)
+        .manage(EventHub::listen(workers))
+        .mount(
+            "/",
+            routes![
+                stream,
+                movies,
+                stream_today,
+                thumbnails,
+                thumbnails_today,
+                capture_time,
+                capture_time_today,
+                locate,
+                sign,
+                sign_today,
+                sign_events,
+                delete_movie,
+                pin_movie,
+                unpin_movie,
+                update_movie,
+                search,
+                add_tags,
+                remove_tag,
+                calendar,
+                events,
+                encoding,
+                failures,
+                metrics,
+                health,
+                ready
+            ],
+        )
+        .register(catchers![
+            errors::bad_request,
+            errors::unauthorized,
+            errors::forbidden,
+            errors::not_found,
+            errors::conflict,
+            errors::unprocessable_entity,
+            errors::internal_error
+        ])
         .launch();
 }
@@ -0,0 +1,183 @@
+use crate::catalog::CatalogMovie;
+use crate::errors::ApiError;
+use crate::MOVIES_FOLDER_ROOT;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Catalog metadata of a movie, stored next to it: 1234.mp4 -> 1234.meta.json
+/// camera_api reads it when stitching, so pins, tags and notes of the hourly clips carry over
+/// to the day movie.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct MovieMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// pinned movies are protected from deletion
+    #[serde(default)]
+    pub pinned: bool,
+    /// fields written by camera_api, kept untouched on updates
+    #[serde(flatten)]
+    pub recorder: serde_json::Map<String, serde_json::Value>,
+}
+
+/// What users wrote about a movie, as shown in the listings. Unset fields are omitted so
+/// clients of the original listing format are unaffected.
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+}
+
+impl From<&MovieMetadata> for Annotations {
+    fn from(metadata: &MovieMetadata) -> Self {
+        Self {
+            title: metadata.title.clone(),
+            notes: metadata.notes.clone(),
+            tags: metadata.tags.clone(),
+            pinned: metadata.pinned,
+        }
+    }
+}
+
+/// Body of POST /movies/<id>/tags
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewTags {
+    pub tags: Vec<String>,
+}
+
+/// Fields of a PATCH /movies/<id> body, absent fields are left unchanged
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MetadataUpdate {
+    title: Option<String>,
+    notes: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+const SIDECAR_SUFFIXES: [&str; 4] = [".meta.json", ".frames.json", ".thumbs.vtt", ".thumbs.jpg"];
+
+fn metadata_path(movie: &CatalogMovie) -> String {
+    format!(
+        "{}/{}",
+        MOVIES_FOLDER_ROOT,
+        movie.sidecar_path(".meta.json")
+    )
+}
+
+impl MovieMetadata {
+    pub fn read(movie: &CatalogMovie) -> Self {
+        fs::read_to_string(metadata_path(movie))
+            .ok()
+            .and_then(|metadata| serde_json::from_str(&metadata).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes to a temporary file first so camera_api never reads a partial file
+    pub fn write(&self, movie: &CatalogMovie) -> Result<(), ApiError> {
+        let path = metadata_path(movie);
+        let tmp_path = format!("{}.tmp", path);
+        let metadata = serde_json::to_string_pretty(self).expect("Error serializing metadata");
+        fs::write(&tmp_path, metadata)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    pub fn add_tags(&mut self, tags: Vec<String>) {
+        self.tags.extend(tags);
+        self.tags = normalize_tags(std::mem::take(&mut self.tags));
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) {
+        let tag = tag.trim().to_lowercase();
+        self.tags.retain(|t| *t != tag);
+    }
+
+    /// Whether the title, notes or tags contain the text, ignoring case
+    pub fn matches_text(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        let contains = |field: &Option<String>| {
+            field
+                .as_ref()
+                .map_or(false, |field| field.to_lowercase().contains(&text))
+        };
+        contains(&self.title)
+            || contains(&self.notes)
+            || self.tags.iter().any(|tag| tag.contains(&text))
+    }
+
+    pub fn apply(&mut self, update: MetadataUpdate) {
+        if let Some(title) = update.title {
+            self.title = Some(title).filter(|t| !t.is_empty());
+        }
+        if let Some(notes) = update.notes {
+            self.notes = Some(notes).filter(|n| !n.is_empty());
+        }
+        if let Some(tags) = update.tags {
+            self.tags = normalize_tags(tags);
+        }
+    }
+}
+
+/// Tags are trimmed, lowercase and unique
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+/// Deletes the movie and every file stored next to it. The thumbnails track of a day movie
+/// points to the sprites of its hourly clips, which are deleted too.
+pub fn delete_movie(movie: &CatalogMovie) -> Result<(), ApiError> {
+    if MovieMetadata::read(movie).pinned {
+        return Err(ApiError::conflict(format!(
+            "Movie {} is pinned, unpin it before deleting",
+            movie.id.0
+        )));
+    }
+    info!("Deleting movie {}", movie.full_path());
+    let vtt_path = format!(
+        "{}/{}",
+        MOVIES_FOLDER_ROOT,
+        movie.sidecar_path(".thumbs.vtt")
+    );
+    if let Ok(vtt) = fs::read_to_string(&vtt_path) {
+        let sprites: BTreeSet<&str> = vtt
+            .lines()
+            .filter(|line| line.contains("#xywh="))
+            .filter_map(|line| line.split('#').next())
+            .filter(|sprite| !sprite.contains('/') && sprite.ends_with(".thumbs.jpg"))
+            .collect();
+        let full_path = movie.full_path();
+        let movie_dir = Path::new(&full_path)
+            .parent()
+            .expect("Movie has no parent dir");
+        for sprite in sprites {
+            let _ = fs::remove_file(movie_dir.join(sprite));
+        }
+    }
+    for suffix in SIDECAR_SUFFIXES.iter() {
+        let _ = fs::remove_file(format!(
+            "{}/{}",
+            MOVIES_FOLDER_ROOT,
+            movie.sidecar_path(suffix)
+        ));
+    }
+    fs::remove_file(movie.full_path())?;
+    Ok(())
+}
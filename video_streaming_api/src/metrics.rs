@@ -0,0 +1,110 @@
+use crate::catalog::{catalog, MovieKind};
+use crate::STREAM_ROUTE;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::request::Request;
+use rocket::response::Response;
+use std::fmt::Write;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Responses by status class, 1xx to 5xx
+static RESPONSES: [AtomicU64; 5] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static STREAMED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Counts the responses and the bytes sent by /stream
+pub struct RequestMetrics;
+
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Response,
+        }
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let class = (response.status().code / 100) as usize;
+        if let Some(responses) = class.checked_sub(1).and_then(|i| RESPONSES.get(i)) {
+            responses.fetch_add(1, Ordering::Relaxed);
+        }
+        // SeekStream sets the length of the range it sends
+        if request.uri().path().starts_with(STREAM_ROUTE) {
+            let length = response
+                .headers()
+                .get_one("Content-Length")
+                .and_then(|length| length.parse::<u64>().ok());
+            if let Some(length) = length {
+                STREAMED_BYTES.fetch_add(length, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Prometheus text exposition of the API metrics and of the catalog
+pub fn render() -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP timelapse_http_responses_total HTTP responses sent"
+    );
+    let _ = writeln!(out, "# TYPE timelapse_http_responses_total counter");
+    for (i, responses) in RESPONSES.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "timelapse_http_responses_total{{status=\"{}xx\"}} {}",
+            i + 1,
+            responses.load(Ordering::Relaxed)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP timelapse_streamed_bytes_total Bytes of movies streamed"
+    );
+    let _ = writeln!(out, "# TYPE timelapse_streamed_bytes_total counter");
+    let _ = writeln!(
+        out,
+        "timelapse_streamed_bytes_total {}",
+        STREAMED_BYTES.load(Ordering::Relaxed)
+    );
+
+    let (mut daily, mut hourly) = ((0, 0), (0, 0));
+    for movie in catalog() {
+        let size = fs::metadata(movie.full_path()).map_or(0, |m| m.len());
+        let (count, bytes) = match movie.kind {
+            MovieKind::Daily => &mut daily,
+            MovieKind::Hourly => &mut hourly,
+        };
+        *count += 1;
+        *bytes += size;
+    }
+    let _ = writeln!(out, "# HELP timelapse_catalog_movies Movies in the catalog");
+    let _ = writeln!(out, "# TYPE timelapse_catalog_movies gauge");
+    let _ = writeln!(
+        out,
+        "timelapse_catalog_movies{{kind=\"daily\"}} {}",
+        daily.0
+    );
+    let _ = writeln!(
+        out,
+        "timelapse_catalog_movies{{kind=\"hourly\"}} {}",
+        hourly.0
+    );
+    let _ = writeln!(
+        out,
+        "# HELP timelapse_catalog_bytes Size of the movies in the catalog"
+    );
+    let _ = writeln!(out, "# TYPE timelapse_catalog_bytes gauge");
+    let _ = writeln!(out, "timelapse_catalog_bytes{{kind=\"daily\"}} {}", daily.1);
+    let _ = writeln!(
+        out,
+        "timelapse_catalog_bytes{{kind=\"hourly\"}} {}",
+        hourly.1
+    );
+    out
+}
@@ -0,0 +1,43 @@
+use crate::catalog::{catalog, AvailableMovies, CatalogMovie};
+use crate::metadata::MovieMetadata;
+
+/// Criteria of GET /search, movies must match all the given ones
+#[derive(Default, Clone, Debug)]
+pub struct SearchQuery {
+    /// text contained in the title, notes or tags
+    pub text: Option<String>,
+    pub tag: Option<String>,
+    /// unix timestamps (in seconds) bounding when the movies started recording
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// Movies matching the query, oldest first. The filter decides which movies may be returned
+/// at all, for instance the ones the user is allowed to watch.
+pub fn search<F>(query: &SearchQuery, filter: F) -> AvailableMovies
+where
+    F: Fn(&CatalogMovie) -> bool,
+{
+    let tag = query.tag.as_ref().map(|tag| tag.trim().to_lowercase());
+    let mut movies = catalog();
+    movies.sort_by_key(|movie| movie.id.0);
+    let mut results = AvailableMovies::default();
+    for movie in movies {
+        if !filter(&movie)
+            || query.from.map_or(false, |from| movie.id.0 < from)
+            || query.to.map_or(false, |to| movie.id.0 > to)
+        {
+            continue;
+        }
+        let metadata = MovieMetadata::read(&movie);
+        let tag_matches = tag.as_ref().map_or(true, |tag| metadata.tags.contains(tag));
+        let text_matches = query
+            .text
+            .as_ref()
+            .map_or(true, |text| metadata.matches_text(text));
+        if tag_matches && text_matches {
+            results.push(&movie, &metadata);
+        }
+    }
+    results
+}
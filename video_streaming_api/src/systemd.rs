@@ -0,0 +1,20 @@
+use log::error;
+use std::os::unix::net::UnixDatagram;
+
+/// Sends a state such as "READY=1" to systemd when running as a Type=notify service, see
+/// sd_notify(3). Does nothing when started by other means.
+pub fn notify(state: &str) {
+    let socket_path = match std::env::var("NOTIFY_SOCKET") {
+        Ok(socket_path) => socket_path,
+        Err(_) => return,
+    };
+    if socket_path.starts_with('@') {
+        error!("Abstract NOTIFY_SOCKET {} is not supported", socket_path);
+        return;
+    }
+    let sent =
+        UnixDatagram::unbound().and_then(|socket| socket.send_to(state.as_bytes(), &socket_path));
+    if let Err(e) = sent {
+        error!("Error notifying systemd of {}: {}", state, e);
+    }
+}